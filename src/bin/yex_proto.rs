@@ -1,5 +1,6 @@
 use yex::session::{Participant, Experiment,Session};
 use yex::output::YexRecord;
+use yex::{Input, NaviEvent};
 use std::sync::mpsc::channel;
 use std::sync::{Arc, Mutex};
 use std::thread::{self, Builder};
@@ -15,18 +16,23 @@ fn main() {
     let session = Arc::new(Mutex::new(Session::new(exp, part)));
     // Starting the event recorder channel
     let (event_snd, event_rec)  = channel::<YexRecord>();
+    // Key input channel, kept open for the whole session
+    let (key_snd, key_rec) = channel::<Input>();
+    // Going forward through welcome, consent and demographics
+    for _ in 0..3 {key_snd.send(Input::Navi(NaviEvent::Forward)).unwrap()}
     // Detached mock task to receive and print the events
     thread::spawn(move || {
-        loop {match event_rec.recv() {
-            Ok(r) => {println!("{:?}",r)}, 
-            _ => {}}}});
+        while let Ok(r) = event_rec.recv() {println!("{:?}",r)}});
     // Building the session thread, because we want it to return a value
     let builder = Builder::new();
     let join_handle = 
-        builder.spawn(move || {yex::demo(session, event_snd)}).unwrap();
+        builder.spawn(move || {yex::demo(session, event_snd, key_rec)}).unwrap();
     match join_handle.join() {
-        Ok(obs) => 
+        Ok(Ok((obs, _summary))) => 
             {println!("{} observations collected", obs.len())},
+        Ok(Err(aborted)) => 
+            {println!("Session aborted ({:?}) after {} observations", 
+                      aborted.error, aborted.observations.len())},
         Err(_) => println!("Session failed")
         
     }
@@ -17,6 +17,7 @@
 /// 
 
 mod egui;
+pub mod font;
 
 pub use std::time::{Instant, Duration};
 pub use std::thread::sleep;
@@ -26,12 +27,92 @@ pub use output::YexEvent;
 //use futures::channel::mpsc;
 pub use isolang::Language;
 
+/// Durations as milliseconds
+/// 
+/// for readable experiment files, e.g. `Blank = 500`. 
+/// Whole milliseconds are integers, shorter durations fractions, e.g. `Blank = 0.5`.
+/// Use with `#[serde(with = "crate::ms")]`, 
+/// option and pair are for optional durations and (thing, duration) pairs.
+#[cfg(feature = "serde")]
+mod ms {
+    use super::Duration;
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+    use serde::de::Error;
+
+    /// A duration as it is written
+    struct Ms(Duration);
+
+    impl Serialize for Ms {
+        fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+            if self.0.subsec_nanos() % 1_000_000 == 0 {
+                (self.0.as_millis() as u64).serialize(s)
+            } else {
+                (self.0.as_nanos() as f64 / 1e6).serialize(s)
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Ms {
+        fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+            let ms = f64::deserialize(d)?;
+            if !ms.is_finite() || ms < 0. {
+                return Err(D::Error::custom(format!("{ms} is not a duration in milliseconds")))
+            }
+            Ok(Ms(Duration::from_nanos((ms * 1e6).round() as u64)))
+        }
+    }
+
+    pub fn serialize<S: Serializer>(dur: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        Ms(*dur).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        Ok(Ms::deserialize(d)?.0)
+    }
+
+    pub mod option {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(dur: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+            dur.map(Ms).serialize(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+            Ok(Option::<Ms>::deserialize(d)?.map(|Ms(dur)| dur))
+        }
+    }
+
+    pub mod pair {
+        use super::*;
+
+        pub fn serialize<T: Serialize, S: Serializer>(pair: &Option<(T, Duration)>, s: S) 
+                -> Result<S::Ok, S::Error> {
+            pair.as_ref().map(|(thing, dur)| (thing, Ms(*dur))).serialize(s)
+        }
+
+        pub fn deserialize<'de, T: Deserialize<'de>, D: Deserializer<'de>>(d: D) 
+                -> Result<Option<(T, Duration)>, D::Error> {
+            Ok(Option::<(T, Ms)>::deserialize(d)?.map(|(thing, Ms(dur))| (thing, dur)))
+        }
+    }
+}
+
 /// Input events
 pub type Text = String;
 pub type Key = char;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum NaviEvent{Back, Forward, Quit}
 
+/// What arrives on the input channel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    Key(Key),
+    /// a key going up again, only needed for chords
+    Release(Key),
+    Navi(NaviEvent),
+}
+
 pub enum Event {
     Response(),
     InputEvent,
@@ -42,27 +123,35 @@ pub enum Event {
 /// 
 /// cycles through a brief demo experiment
 /// Collects virtual responses and completes with a Vector of Observations
+/// and a summary, which is also printed.
+/// When the participant quits, the demo ends early, 
+/// other interruptions abort it.
 
 use session::*;
-use trial::Observation;
-use output::YexRecord;
-pub fn demo(session: Arc<Mutex<Session>>, events_out: Sender<output::YexRecord>) 
-        -> Vec<trial::Observation>{
-    let mut obs_out: Vec<Observation> = Vec::new();
+use output::{YexRecord, Summary};
+pub fn demo(session: Arc<Mutex<Session>>, events_out: Sender<output::YexRecord>, 
+            input: Receiver<Input>) 
+        -> Result<(Vec<trial::Observation>, DemoSummary), output::Aborted>{
+    let start = Instant::now();
     let mut session = session.lock().unwrap();
     events_out.send(YexEvent::Session(session.state.clone()).into()).unwrap();
-    session.state = State::Welcome;
-    sleep(Duration::from_millis(500));
-    for block in session.exp.blocks.iter(){
-        let obs 
-            = block.clone().run(events_out.clone());
-        match obs {
-            Some(mut obs) => {obs_out.append(&mut obs);},
-            None => {println!("No observations collected")},
-        }
-    }
-    session.state = State::Goodbye;
-    return obs_out
+    let obs_out = session.run(events_out, input, None)?;
+    let summary = DemoSummary{summary: output::summarize(&obs_out), duration: start.elapsed()};
+    println!("{}", summary);
+    Ok((obs_out, summary))
+}
+
+/// Summary of a demo run
+#[derive(Clone, Debug, PartialEq)]
+pub struct DemoSummary {
+    pub summary: Summary,
+    pub duration: Duration,
+}
+
+impl std::fmt::Display for DemoSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} in {:.1} s", self.summary, self.duration.as_secs_f32())
+    }
 }
 
 
@@ -76,18 +165,77 @@ pub fn demo(session: Arc<Mutex<Session>>, events_out: Sender<output::YexRecord>)
 
  
 pub mod session {
-    use super::{Instant, Language, Text};
-    use super::block::Block;
+    use super::{Instant, Duration, Language, Text, Key, sleep, Sender, Receiver, Input};
+    use super::block::{Block, BlockContext, JsonOut, ProbeResponse};
+    use super::output::{LintWarning, ValidationError, Triggers, YexRecord, YexEvent, YexError, Aborted};
+    use super::input::{Responder, GazeSource, GazeSample, Region};
+    use super::trial::{Trial, Observation};
+    use std::collections::{HashSet, HashMap};
+    use rand::{Rng, SeedableRng};
+    use rand::seq::SliceRandom;
 
-    #[derive(Debug, Clone)]
+    /// Random number generator of a session
+    /// 
+    /// all random draws of a session come from here, 
+    /// which makes a session reproducible from its seed.
+    pub type SessionRng = rand_chacha::ChaCha8Rng;
+
+    /// + allowed_keys is a whitelist of keys the responder lets through at all.
+    ///   Everything else is dropped before it reaches a trial. 
+    ///   None lets all keys pass.
+    /// + seed for the session RNG, by default the participant id
+    /// + trigger_codes map conditions to trigger codes, sent to trigger_out 
+    ///   at stimulus onset. Other trials send default_trigger.
+    /// + observations are recorded when the session has run, 
+    ///   the answers to attention probes in probe_responses
+    /// + stream_stdout_json prints every observation as it completes, 
+    ///   as one line of JSON on stdout, e.g. for piping into jq. 
+    ///   The lines go to json_out, which is stdout unless it is replaced, e.g. by a file.
+    /// + gaze is an eye tracker's stream of samples, for trials with a fixation check, 
+    ///   flips are the frame flips of the renderer, see FlipRecorder. 
+    ///   They go to the responder for a run and come back afterwards, 
+    ///   a clone of the session has none.
+    #[derive(Debug)]
     pub struct Session {
         pub id: Instant,
         pub part: Participant,
         pub exp: Experiment,
         pub state: State,
+        pub allowed_keys: Option<HashSet<Key>>,
+        pub seed: u64,
+        pub trigger_codes: HashMap<String, u8>,
+        pub default_trigger: u8,
+        pub trigger_out: Option<Sender<u8>>,
+        pub observations: Vec<Observation>,
+        pub probe_responses: Vec<ProbeResponse>,
+        pub stream_stdout_json: bool,
+        pub json_out: JsonOut,
+        pub gaze: Option<Receiver<GazeSample>>,
+        pub flips: Option<Receiver<Instant>>,
+    }
+
+    impl Clone for Session {
+        fn clone(&self) -> Self {
+            Session{id: self.id,
+                    part: self.part.clone(),
+                    exp: self.exp.clone(),
+                    state: self.state.clone(),
+                    allowed_keys: self.allowed_keys.clone(),
+                    seed: self.seed,
+                    trigger_codes: self.trigger_codes.clone(),
+                    default_trigger: self.default_trigger,
+                    trigger_out: self.trigger_out.clone(),
+                    observations: self.observations.clone(),
+                    probe_responses: self.probe_responses.clone(),
+                    stream_stdout_json: self.stream_stdout_json,
+                    json_out: self.json_out.clone(),
+                    gaze: None,
+                    flips: None}
+        }
     }
 
     #[derive(Debug, Clone)]
+    #[allow(clippy::large_enum_variant)]
     pub enum State {
         Init,
         Welcome,
@@ -100,9 +248,330 @@ pub mod session {
     impl Session {
         pub fn new(exp: Experiment, part: Participant) -> Self{
             Session{id: Instant::now(),
-                    part: part,
-                    exp: exp,
-                    state: State::Init}
+                    seed: part.id as u64,
+                    part,
+                    exp,
+                    state: State::Init,
+                    allowed_keys: None,
+                    trigger_codes: HashMap::new(),
+                    default_trigger: 1,
+                    trigger_out: None,
+                    observations: Vec::new(),
+                    probe_responses: Vec::new(),
+                    stream_stdout_json: false,
+                    json_out: JsonOut::stdout(),
+                    gaze: None,
+                    flips: None}
+        }
+
+        pub fn rng(&self) -> SessionRng {
+            SessionRng::seed_from_u64(self.seed)
+        }
+
+        /// The responder for the input, with the session's keys, triggers, gaze and flips
+        /// 
+        /// Gaze and flips move to the responder, see release.
+        pub fn responder(&mut self, input: Receiver<Input>) -> Responder {
+            let mut responder = Responder::new(input);
+            responder.allowed_keys = self.allowed_keys.clone();
+            responder.triggers = self.triggers();
+            responder.gaze = self.gaze.take();
+            responder.flips = self.flips.take();
+            responder
+        }
+
+        /// Taking gaze and flips back from a responder after a run
+        pub fn release(&mut self, mut responder: Responder) {
+            self.gaze = responder.gaze.take();
+            self.flips = responder.flips.take();
+        }
+
+        /// Running the blocks in their order, starting at position from
+        /// 
+        /// Observations are tagged with the participant and experiment ids 
+        /// and the index of their block. 
+        /// When the participant quits, the run ends early with the 
+        /// observations so far, other interruptions abort it.
+        pub fn run_blocks(&mut self, from: usize, events_out: Sender<YexRecord>, responder: &mut Responder) 
+                -> Result<Vec<Observation>, Aborted> {
+            let mut rng = self.rng();
+            let order = self.exp.block_order(&mut rng)
+                .map_err(|error| Aborted{error, observations: Vec::new()})?;
+            let seeds = Session::block_seeds(&order, &mut rng);
+            // the ids are shared by all observations
+            let mut context = BlockContext{participant: self.part.id, 
+                                           experiment: self.exp.id.as_str().into(), 
+                                           block: 0,
+                                           json_out: self.stream_stdout_json.then(|| self.json_out.clone())};
+            let mut obs_out: Vec<Observation> = Vec::new();
+            for (index, seed) in order.into_iter().zip(seeds).skip(from) {
+                let mut block = self.exp.blocks[index].clone();
+                block.prepare().map_err(|error| Aborted{error, observations: Vec::new()}.after(obs_out.clone()))?;
+                self.enter(State::Blocks(block.clone()), &events_out);
+                context.block = index;
+                let obs = block.run(&context, events_out.clone(), responder, &mut SessionRng::seed_from_u64(seed));
+                self.probe_responses.append(&mut block.probe_responses);
+                match obs {
+                    Ok(mut obs) => {obs_out.append(&mut obs);},
+                    // participant quit
+                    Err(Aborted{error: YexError::PartInterrupt(), mut observations}) 
+                        => {obs_out.append(&mut observations); break},
+                    Err(aborted) => return Err(aborted.after(obs_out)),
+                }
+            }
+            Ok(obs_out)
+        }
+
+        /// Running the session through its states
+        /// 
+        /// Welcome, Consent and Demographics wait for the participant 
+        /// to go Forward, then the blocks run, each in State::Blocks, and 
+        /// the session ends in Goodbye. When the participant quits, 
+        /// the session ends early with the observations so far.
+        /// 
+        /// With a timeout, a watchdog aborts the run when it takes longer, 
+        /// e.g. waiting for keys in CI. The aborted run keeps the observations so far.
+        /// The observations of the run are recorded in the session. 
+        /// An experiment that does not validate does not run, see Experiment::validate.
+        pub fn run(&mut self, events_out: Sender<YexRecord>, input: Receiver<Input>, 
+                   timeout: Option<Duration>) 
+                -> Result<Vec<Observation>, Aborted> {
+            self.exp.validate()
+                .map_err(|error| Aborted{error: YexError::Invalid(error), observations: Vec::new()})?;
+            self.run_from(0, events_out, input, timeout)
+        }
+
+        /// Running through the states, with the blocks from position from
+        fn run_from(&mut self, from: usize, events_out: Sender<YexRecord>, input: Receiver<Input>, 
+                    timeout: Option<Duration>) 
+                -> Result<Vec<Observation>, Aborted> {
+            let mut responder = self.responder(input);
+            responder.watchdog = timeout.map(|timeout| Instant::now() + timeout);
+            let obs = self.run_states(from, &events_out, &mut responder);
+            self.release(responder);
+            self.enter(State::Goodbye, &events_out);
+            self.observations = match &obs {
+                Ok(obs) => obs.clone(),
+                Err(aborted) => aborted.observations.clone(),
+            };
+            obs
+        }
+
+        fn run_states(&mut self, from: usize, events_out: &Sender<YexRecord>, responder: &mut Responder) 
+                -> Result<Vec<Observation>, Aborted> {
+            for state in [State::Welcome, State::Consent, State::Demographics] {
+                self.enter(state, events_out);
+                match responder.wait_forward(None) {
+                    Ok(_) => {},
+                    Err(YexError::PartInterrupt()) => return Ok(Vec::new()),
+                    Err(error) => return Err(Aborted{error, observations: Vec::new()}),
+                }
+            }
+            self.run_blocks(from, events_out.clone(), responder)
+        }
+
+        /// Changing the state and telling the renderer
+        fn enter(&mut self, state: State, events_out: &Sender<YexRecord>) {
+            self.state = state;
+            events_out.send(YexEvent::Session(self.state.clone()).into()).unwrap();
+        }
+
+        /// Calibrating an eye tracker
+        /// 
+        /// presents the targets one after the other, as CalibrationTarget events, 
+        /// and collects CALIBRATION_SAMPLES gaze samples for each, 
+        /// waiting at most CALIBRATION_TIMEOUT. 
+        /// The accuracy of a point is the mean distance of its samples to the target.
+        pub fn calibrate(&self, points: &[Region], source: &mut dyn GazeSource, 
+                         events_out: Sender<YexRecord>) -> CalibrationResult {
+            let points = points.iter()
+                .map(|target| {
+                    events_out.send(YexEvent::CalibrationTarget(*target).into()).unwrap();
+                    let deadline = Instant::now() + CALIBRATION_TIMEOUT;
+                    let mut distances = Vec::new();
+                    while distances.len() < CALIBRATION_SAMPLES {
+                        let Some(sample) = source.next_sample(deadline) else {break};
+                        distances.push(target.distance(&sample));
+                    }
+                    let error = if distances.is_empty() {None} 
+                        else {Some(distances.iter().sum::<f32>() / distances.len() as f32)};
+                    CalibrationPoint{target: *target, samples: distances.len(), error}
+                })
+                .collect();
+            CalibrationResult{points}
+        }
+
+        /// Resuming a session at a block
+        /// 
+        /// e.g. after a technical failure. The blocks before the named block 
+        /// are skipped, which is logged as BlockSkipped events. 
+        /// Otherwise like run, from Welcome to Goodbye and with the timeout.
+        pub fn run_from_block(&mut self, block_name: &str, events_out: Sender<YexRecord>, 
+                              input: Receiver<Input>, timeout: Option<Duration>) 
+                -> Result<Vec<Observation>, Aborted> {
+            self.exp.validate()
+                .map_err(|error| Aborted{error: YexError::Invalid(error), observations: Vec::new()})?;
+            let order = self.exp.block_order(&mut self.rng())
+                .map_err(|error| Aborted{error, observations: Vec::new()})?;
+            let Some(from) = order.iter().position(|index| self.exp.blocks[*index].name == block_name) 
+                else {return Err(Aborted{error: YexError::BlockNotFound(block_name.into()), 
+                                         observations: Vec::new()})};
+            for index in order[..from].iter() {
+                events_out.send(YexEvent::BlockSkipped(self.exp.blocks[*index].name.clone()).into()).unwrap();
+            }
+            self.run_from(from, events_out, input, timeout)
+        }
+
+        /// Triggers for the responder, when there is a trigger sink
+        pub fn triggers(&self) -> Option<Triggers> {
+            Some(Triggers{codes: self.trigger_codes.clone(), 
+                          default: self.default_trigger, 
+                          out: self.trigger_out.clone()?})
+        }
+
+        /// The trials of the session in the order they are presented
+        /// 
+        /// None when a block generates its trials, 
+        /// because these depend on the responses.
+        pub fn plan(&self) -> Option<Vec<Trial>> {
+            let mut rng = self.rng();
+            let order = self.exp.block_order(&mut rng).ok()?;
+            let seeds = Session::block_seeds(&order, &mut rng);
+            let mut plan = Vec::new();
+            for (index, seed) in order.into_iter().zip(seeds) {
+                plan.append(&mut self.exp.blocks[index].plan(&mut SessionRng::seed_from_u64(seed))?);
+            }
+            Some(plan)
+        }
+
+        /// Every block runs with its own RNG, seeded from the session RNG 
+        /// up front, so that the trial order does not depend on 
+        /// the draws of earlier blocks, like the ITI
+        fn block_seeds(order: &[usize], rng: &mut SessionRng) -> Vec<u64> {
+            order.iter().map(|_| rng.gen()).collect()
+        }
+
+        /// Verifying a recorded session
+        /// 
+        /// resolves the plan again from the recorded seed and design
+        /// and checks that the recorded observations were presented 
+        /// in this order and with the same design, ignoring the responses. 
+        /// A session that was quit early verifies with the trials it has, 
+        /// one without observations does not verify.
+        pub fn verify_reproducible(recorded: &Session) -> bool {
+            let mut session = Session::new(recorded.exp.clone(), recorded.part.clone());
+            session.seed = recorded.seed;
+            let Some(plan) = session.plan() else {return false};
+            !recorded.observations.is_empty()
+                && recorded.observations.len() <= plan.len()
+                && recorded.observations.iter().zip(plan.iter())
+                    .all(|(obs, trial)| obs.trial.same_design(trial))
+        }
+
+        /// Measuring the timer resolution
+        /// 
+        /// as the shortest of a few delays that were asked for a microsecond. 
+        /// On some platforms this is around 15 ms.
+        pub fn probe_timer_resolution() -> Duration {
+            (0..5)
+                .map(|_| {
+                    let start = Instant::now();
+                    sleep(Duration::from_micros(1));
+                    start.elapsed()
+                })
+                .min()
+                .unwrap_or_default()
+        }
+
+        /// Checking the design before running it
+        /// 
+        /// warns about durations that the timer cannot resolve, 
+        /// which would silently be rounded up, and about correct keys 
+        /// that the trial's advance does not take. 
+        pub fn validate(&self, resolution: Duration) -> Vec<LintWarning> {
+            let mut warnings = Vec::new();
+            for (i, block) in self.exp.blocks.iter().enumerate() {
+                let mut durations: Vec<(String, Duration)> = block.durations().into_iter()
+                    .map(|(what, dur)| (format!("block {}, {}", i + 1, what), dur))
+                    .collect();
+                for (j, trial) in block.trials.iter().enumerate() {
+                    durations.extend(trial.durations().into_iter()
+                        .map(|(what, dur)| (format!("block {}, trial {}, {}", i + 1, j + 1, what), dur)));
+                    warnings.extend(trial.unreachable_correct_keys().into_iter()
+                        .map(|key| LintWarning::CorrectKeyNotAllowed(format!("block {}, trial {}", i + 1, j + 1), key)));
+                }
+                warnings.extend(durations.into_iter()
+                    .filter(|(_, dur)| *dur > Duration::ZERO && *dur < resolution)
+                    .map(|(what, dur)| LintWarning::BelowTimerResolution(what, dur)));
+            }
+            warnings
+        }
+
+        /// The ordered stages a session passes through
+        /// 
+        /// for showing progress, e.g. as a stepper before the run starts.
+        /// Blocks come in their order with their estimated duration, 
+        /// the other stages depend on the participant.
+        pub fn stages(&self) -> Vec<StageDescriptor> {
+            let mut stages = vec![
+                StageDescriptor::new("Welcome", None, None),
+                StageDescriptor::new("Consent", None, None),
+                StageDescriptor::new("Demographics", None, None)];
+            let order = self.exp.block_order(&mut self.rng())
+                .unwrap_or_else(|_| (0..self.exp.blocks.len()).collect());
+            for i in order {
+                let block = &self.exp.blocks[i];
+                stages.push(StageDescriptor::new(&block.name, 
+                                                 Some(i), 
+                                                 Some(block.estimated_duration())));
+            }
+            stages.push(StageDescriptor::new("Goodbye", None, None));
+            stages
+        }
+    }
+
+    /// Gaze samples per calibration target
+    pub const CALIBRATION_SAMPLES: usize = 30;
+    /// Maximum time per calibration target
+    pub const CALIBRATION_TIMEOUT: Duration = Duration::from_secs(3);
+
+    /// Result of a calibration
+    /// 
+    /// + error is the mean distance of the samples to the target, 
+    ///   None without samples
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct CalibrationPoint {
+        pub target: Region,
+        pub samples: usize,
+        pub error: Option<f32>,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct CalibrationResult {
+        pub points: Vec<CalibrationPoint>,
+    }
+
+    impl CalibrationResult {
+        /// Every point has its samples within the target region
+        pub fn success(&self) -> bool {
+            self.points.iter()
+                .all(|point| point.error.map_or(false, |error| error <= point.target.radius))
+        }
+    }
+
+    /// A stage of a session
+    /// 
+    /// + block is the index of the block in the experiment
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct StageDescriptor {
+        pub name: String,
+        pub block: Option<usize>,
+        pub estimated: Option<Duration>,
+    }
+
+    impl StageDescriptor {
+        fn new(name: &str, block: Option<usize>, estimated: Option<Duration>) -> Self {
+            Self{name: name.into(), block, estimated}
         }
     }
 
@@ -141,51 +610,738 @@ pub mod session {
     /// An Experiment is a container for trials arranged in blocks.
     /// 
     /// data-only class as Session is doing the run()
+    /// 
+    /// With random, the blocks run in a random order, except for the 
+    /// pinned_positions, which map a block index to its fixed position, 
+    /// e.g. a baseline first and a washout last.
 
 
-    #[derive(Clone, Debug)]
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
     pub struct Experiment {
         pub id: String,
         pub blocks: Vec<Block>,
         pub instructions: Text,
         pub random: bool,
+        pub pinned_positions: HashMap<usize, usize>,
+    }
+
+    impl Experiment {
+        /// Checking the experiment before it runs
+        /// 
+        /// Block names must be unique, as resuming and skipping find blocks by name. 
+        /// Pins must refer to existing blocks and positions, 
+        /// and no two blocks can share a position.
+        pub fn validate(&self) -> Result<(), ValidationError> {
+            let mut names = HashSet::new();
+            for block in self.blocks.iter() {
+                if !names.insert(block.name.as_str()) {
+                    return Err(ValidationError::DuplicateBlockName(block.name.clone()))
+                }
+            }
+            self.check_pins()
+        }
+
+        fn check_pins(&self) -> Result<(), ValidationError> {
+            let n = self.blocks.len();
+            let mut taken = HashSet::new();
+            for (block, position) in self.pinned_positions.iter() {
+                if *block >= n || *position >= n || !taken.insert(*position) {
+                    return Err(ValidationError::InconsistentPin(*block, *position))
+                }
+            }
+            Ok(())
+        }
+
+        /// The order of blocks as indices, drawn from the session RNG
+        pub fn block_order(&self, rng: &mut SessionRng) -> Result<Vec<usize>, YexError> {
+            self.check_pins().map_err(YexError::Invalid)?;
+            let mut order: Vec<usize> = (0..self.blocks.len()).collect();
+            if !self.random {return Ok(order)}
+            let mut free: Vec<usize> = order.iter().copied()
+                .filter(|block| !self.pinned_positions.contains_key(block))
+                .collect();
+            free.shuffle(rng);
+            let mut free = free.into_iter();
+            for (position, slot) in order.iter_mut().enumerate() {
+                *slot = match self.pinned_positions.iter().find(|(_, pinned)| **pinned == position) {
+                    Some((block, _)) => *block,
+                    None => free.next().unwrap_or(position),
+                };
+            }
+            Ok(order)
+        }
     }
 
     impl Default for Experiment {
         fn default() -> Self {
+            let blocks = (1..=2)
+                .map(|i| Block{name: format!("Block {}", i), ..Block::default()})
+                .collect();
             Self {  id: "Stroop".into(), 
-                    blocks: vec![Block::default();2],
+                    blocks,
                     instructions: "Say the color of the word!".into(),
-                    random: false,}
+                    random: false,
+                    pinned_positions: HashMap::new()}
         }
 }
 
+    /// Building an experiment
+    /// 
+    /// Unset fields keep their defaults. The blocks accumulate in order, 
+    /// without any, the experiment has the default blocks.
+    #[derive(Clone, Debug)]
+    pub struct ExperimentBuilder {
+        experiment: Experiment,
+        blocks: Option<Vec<Block>>,
+    }
+
+    impl Experiment {
+        pub fn builder() -> ExperimentBuilder {
+            ExperimentBuilder{experiment: Experiment::default(), blocks: None}
+        }
+    }
+
+    impl ExperimentBuilder {
+        pub fn id(mut self, id: &str) -> Self {
+            self.experiment.id = id.into();
+            self
+        }
+
+        pub fn instructions(mut self, instructions: &str) -> Self {
+            self.experiment.instructions = instructions.into();
+            self
+        }
+
+        pub fn random(mut self, random: bool) -> Self {
+            self.experiment.random = random;
+            self
+        }
+
+        /// Pinning a block to a position in a random order
+        pub fn pin(mut self, block: usize, position: usize) -> Self {
+            self.experiment.pinned_positions.insert(block, position);
+            self
+        }
+
+        pub fn block(mut self, block: Block) -> Self {
+            self.blocks.get_or_insert_with(Vec::new).push(block);
+            self
+        }
+
+        pub fn build(mut self) -> Experiment {
+            if let Some(blocks) = self.blocks {
+                self.experiment.blocks = blocks;
+            }
+            self.experiment
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::sync::{Arc, Mutex};
+        use crate::{channel, Language, NaviEvent};
+        use crate::output::write_json_line;
+        use crate::testing::forwarded;
+        use crate::block::{Prelude, Relax};
+        use crate::trial::{Advance, Stimulus};
+
+        /// Trials of a block, quick and without input
+        fn quick(trials: Vec<Trial>) -> Block {
+            let trials = trials.into_iter()
+                .map(|trial| Trial{advance: Advance::Wait(Duration::from_millis(1)), ..trial})
+                .collect();
+            Block{prelude: Prelude::Now, relax: Relax::Now, trials, ..Block::default()}
+        }
+
+        /// Two shuffled blocks of words, in random order
+        fn words() -> Experiment {
+            let word = |text: &str| Trial{
+                stimulus: Stimulus::text(Duration::ZERO, text, 14, [0, 0, 0], Language::Eng).unwrap(), 
+                condition: Some(text.into()),
+                ..Trial::default()};
+            let block = |name: &str| Block{name: name.into(), random: true, 
+                                           ..quick(["RED", "GREEN", "BLUE", "YELLOW"].map(word).to_vec())};
+            Experiment{blocks: vec![block("A"), block("B")], random: true, ..Experiment::default()}
+        }
+
+        /// A session of one trial with a fixation check, 
+        /// and gaze samples at x from now on, every 5 ms
+        fn fixating(x: f32) -> Session {
+            let fixation = crate::trial::FixationCheck{radius: 0.1, max_break: Duration::from_millis(20)};
+            let trial = Trial{advance: Advance::Wait(Duration::from_millis(100)), 
+                              fixation: Some(fixation), ..Trial::default()};
+            let block = Block{trials: vec![trial], ..quick(Vec::new())};
+            let mut session = Session::new(Experiment{blocks: vec![block], ..Experiment::default()}, 
+                                           Participant::default());
+            let (samples, gaze) = channel();
+            std::thread::spawn(move || 
+                for _ in 0..60 {
+                    let sample = GazeSample{time: Instant::now(), x, y: 0.5};
+                    if samples.send(sample).is_err() {break}
+                    sleep(Duration::from_millis(5));
+                });
+            session.gaze = Some(gaze);
+            session
+        }
+
+        #[test]
+        fn fixation_break_rejects_trial() {
+            let (events_out, _events) = channel();
+            let mut session = fixating(0.9);
+            let obs = session.run(events_out, forwarded(), None).unwrap();
+            assert!(obs[0].rejected);
+            assert_eq!(obs[0].fixation_breaks.len(), 1);
+            assert!(obs[0].fixation_breaks[0].1 > Duration::from_millis(20));
+            assert!(session.gaze.is_some());
+        }
+
+        #[test]
+        fn steady_fixation_is_kept() {
+            let (events_out, _events) = channel();
+            let obs = fixating(0.5).run(events_out, forwarded(), None).unwrap();
+            assert!(!obs[0].rejected);
+        }
+
+        #[test]
+        fn observations_carry_their_ids() {
+            let (events_out, _events) = channel();
+            let block = |name: &str| Block{name: name.into(), ..quick(vec![Trial::default(); 2])};
+            let exp = Experiment{blocks: vec![block("A"), block("B")], ..Experiment::default()};
+            let experiment = exp.id.clone();
+            let part = Participant{id: 7, ..Participant::default()};
+            let obs = Session::new(exp, part).run(events_out, forwarded(), None).unwrap();
+            let blocks: Vec<usize> = obs.iter().map(|obs| obs.block).collect();
+            assert_eq!(blocks, vec![0, 0, 1, 1]);
+            let trials: Vec<usize> = obs.iter().map(|obs| obs.trial_index).collect();
+            assert_eq!(trials, vec![0, 1, 0, 1]);
+            assert!(obs.iter().all(|obs| obs.participant == 7 && *obs.experiment == *experiment));
+        }
+
+        #[test]
+        #[cfg(feature = "serde")]
+        fn experiment_loads_from_toml() {
+            let exp: Experiment = toml::from_str(r#"
+                id = "stroop"
+
+                [[blocks]]
+                name = "practice"
+                prelude = "Now"
+                relax = { Wait = 1500 }
+
+                [[blocks.trials]]
+                condition = "congruent"
+                stimulus = { Blank = 0.5 }
+                advance = { KeysMaxWait = [["f", "j"], 2000] }
+                correct = ["f"]
+
+                [[blocks]]
+                name = "test"
+                random = true
+            "#).unwrap();
+            assert_eq!(exp.id, "stroop");
+            let names: Vec<&str> = exp.blocks.iter().map(|block| block.name.as_str()).collect();
+            assert_eq!(names, ["practice", "test"]);
+            let practice = &exp.blocks[0];
+            assert_eq!(practice.prelude, Prelude::Now);
+            assert!(matches!(practice.relax, Relax::Wait(dur) if dur == Duration::from_millis(1500)));
+            assert_eq!(practice.trials, vec![Trial{stimulus: Stimulus::Blank(Duration::from_micros(500)),
+                                                   advance: Advance::KeysMaxWait(vec!['f', 'j'], Duration::from_secs(2)),
+                                                   correct: vec!['f'],
+                                                   condition: Some("congruent".into()),
+                                                   ..Trial::default()}]);
+            assert!(exp.blocks[1].random);
+            assert_eq!(exp.blocks[1].trials, Block::default().trials);
+            assert_eq!(exp.validate(), Ok(()));
+        }
+
+        /// A renderer that flips with every participant frame
+        struct Flipping(Option<Instant>);
+
+        impl crate::render::Renderer for Flipping {
+            fn present_participant(&mut self, _: &YexEvent) {
+                self.0 = Some(Instant::now());
+            }
+
+            fn present_experimenter(&mut self, _: &str) {}
+
+            fn last_flip_time(&self) -> Option<Instant> {
+                self.0
+            }
+        }
+
+        #[test]
+        fn flips_are_recorded() {
+            use crate::render::{Renderer, FlipRecorder};
+            let (events_out, events) = channel::<YexRecord>();
+            let (flips_out, flips) = channel();
+            let renderer = std::thread::spawn(move || {
+                let mut renderer = FlipRecorder::new(Flipping(None), flips_out);
+                for record in events {
+                    renderer.render(&record);
+                }
+            });
+            let trial = Trial{advance: Advance::Wait(Duration::from_millis(20)), ..Trial::default()};
+            let mut session = Session::new(Experiment{blocks: vec![quick(Vec::new())], ..Experiment::default()}, 
+                                           Participant::default());
+            session.exp.blocks[0].trials = vec![trial];
+            session.flips = Some(flips);
+            let obs = session.run(events_out, forwarded(), None).unwrap();
+            // Init, Prelude and Present
+            assert_eq!(obs[0].flip_times.len(), 3);
+            assert!(obs[0].flip_times.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(session.flips.is_some());
+            renderer.join().unwrap();
+        }
+
+        #[test]
+        fn sub_resolution_duration_warns() {
+            let trial = Trial{prelude: crate::trial::Prelude::Now,
+                              stimulus: Stimulus::Blank(Duration::ZERO),
+                              advance: Advance::Wait(Duration::from_millis(5)), ..Trial::default()};
+            let block = Block{trials: vec![trial], ..quick(Vec::new())};
+            let session = Session::new(Experiment{blocks: vec![block], ..Experiment::default()}, 
+                                       Participant::default());
+            let warnings = session.validate(Duration::from_millis(15));
+            assert_eq!(warnings, vec![LintWarning::BelowTimerResolution("block 1, trial 1, advance".into(), 
+                                                                        Duration::from_millis(5))]);
+            assert!(session.validate(Duration::from_millis(1)).is_empty());
+            assert!(Session::probe_timer_resolution() > Duration::ZERO);
+        }
+
+        #[test]
+        fn default_session_reaches_goodbye() {
+            let (events_out, events) = channel();
+            let mut session = Session::new(Experiment::default(), Participant::default());
+            // forwarding through every state, prelude and relax, the trials take their time
+            let input = crate::testing::typed(vec![Input::Navi(NaviEvent::Forward); 200], Duration::from_millis(20));
+            let obs = session.run(events_out, input, None).unwrap();
+            let trials: usize = session.exp.blocks.iter().map(|block| block.trials.len()).sum();
+            assert_eq!(obs.len(), trials);
+            assert!(matches!(session.state, State::Goodbye));
+            let states: Vec<&'static str> = events.try_iter()
+                .filter_map(|YexRecord(_, event)| match event {
+                    YexEvent::Session(state) => Some(match state {
+                        State::Init => "Init",
+                        State::Welcome => "Welcome",
+                        State::Consent => "Consent",
+                        State::Demographics => "Demographics",
+                        State::Blocks(_) => "Blocks",
+                        State::Goodbye => "Goodbye"}),
+                    _ => None})
+                .collect();
+            assert_eq!(states, ["Welcome", "Consent", "Demographics", "Blocks", "Blocks", "Goodbye"]);
+        }
+
+        #[test]
+        fn stages_list_the_default_blocks() {
+            let session = Session::new(Experiment::default(), Participant::default());
+            let stages = session.stages();
+            let names: Vec<&str> = stages.iter().map(|stage| stage.name.as_str()).collect();
+            assert_eq!(names, ["Welcome", "Consent", "Demographics", "Block 1", "Block 2", "Goodbye"]);
+            assert_eq!(stages[3].block, Some(0));
+            assert_eq!(stages[4].block, Some(1));
+            assert_eq!(stages[3].estimated, Some(session.exp.blocks[0].estimated_duration()));
+            assert!(stages.iter().filter(|stage| stage.block.is_none()).all(|stage| stage.estimated.is_none()));
+        }
+
+        /// Input that goes through the welcome states, and later sends one more
+        fn forwarded_then(later: NaviEvent, after: Duration) -> Receiver<Input> {
+            let (keys, input) = channel();
+            for _ in 0..3 {
+                keys.send(Input::Navi(NaviEvent::Forward)).unwrap();
+            }
+            std::thread::spawn(move || {
+                sleep(after);
+                let _ = keys.send(Input::Navi(later));
+            });
+            input
+        }
+
+        #[test]
+        fn quit_in_relax_keeps_the_block() {
+            let (events_out, _events) = channel();
+            let block = |name: &str| Block{name: name.into(), relax: Relax::Wait(Duration::from_secs(5)), 
+                                           ..quick(vec![Trial::default(); 2])};
+            let mut session = Session::new(Experiment{blocks: vec![block("A"), block("B")], ..Experiment::default()}, 
+                                           Participant::default());
+            let start = Instant::now();
+            let obs = session.run(events_out, forwarded_then(NaviEvent::Quit, Duration::from_millis(100)), None).unwrap();
+            assert!(start.elapsed() < Duration::from_secs(1));
+            assert_eq!(obs.len(), 2);
+            assert!(obs.iter().all(|obs| obs.block == 0));
+            assert!(matches!(session.state, State::Goodbye));
+        }
+
+        #[test]
+        fn forward_cuts_relax_short() {
+            let (events_out, _events) = channel();
+            let block = Block{relax: Relax::Wait(Duration::from_secs(5)), ..quick(vec![Trial::default(); 2])};
+            let mut session = Session::new(Experiment{blocks: vec![block], ..Experiment::default()}, 
+                                           Participant::default());
+            let start = Instant::now();
+            let obs = session.run(events_out, forwarded_then(NaviEvent::Forward, Duration::from_millis(100)), None).unwrap();
+            assert!(start.elapsed() < Duration::from_secs(1), "{:?}", start.elapsed());
+            assert_eq!(obs.len(), 2);
+        }
+
+        #[test]
+        fn builders_default_and_accumulate() {
+            assert_eq!(Experiment::builder().build(), Experiment::default());
+            let block = |name: &str| Block::builder().name(name).build();
+            let exp = Experiment::builder().id("flanker").random(true)
+                .block(block("A")).block(block("B")).block(block("C"))
+                .build();
+            let names: Vec<&str> = exp.blocks.iter().map(|block| block.name.as_str()).collect();
+            assert_eq!(names, ["A", "B", "C"]);
+            assert_eq!(exp, Experiment{id: "flanker".into(), random: true, 
+                                       blocks: vec![block("A"), block("B"), block("C")], 
+                                       ..Experiment::default()});
+        }
+
+        #[test]
+        fn quit_with_a_response_interrupts_the_trial() {
+            let (events_out, _events) = channel();
+            let trial = Trial{advance: Advance::KeysMaxWait(vec!['f', 'j'], Duration::from_millis(500)), 
+                              correct: vec!['f'], ..Trial::default()};
+            let block = Block{prelude: Prelude::Now, relax: Relax::Now, trials: vec![trial; 2], ..Block::default()};
+            let mut session = Session::new(Experiment{blocks: vec![block], ..Experiment::default()}, 
+                                           Participant::default());
+            let (keys, input) = channel();
+            for _ in 0..3 {
+                keys.send(Input::Navi(NaviEvent::Forward)).unwrap();
+            }
+            std::thread::spawn(move || {
+                sleep(Duration::from_millis(50));
+                // both arrive before the trial reads the key
+                let _ = keys.send(Input::Key('f'));
+                let _ = keys.send(Input::Navi(NaviEvent::Quit));
+            });
+            let obs = session.run(events_out, input, None).unwrap();
+            assert_eq!(obs.len(), 1);
+            assert_eq!(obs[0].response, crate::trial::Response::Interrupted);
+            assert!(matches!(session.state, State::Goodbye));
+        }
+
+        #[test]
+        fn keys_off_the_whitelist_never_reach_a_trial() {
+            let (events_out, _events) = channel();
+            let trial = Trial{prelude: crate::trial::Prelude::Now, 
+                              advance: Advance::KeysMaxWait(vec!['f', 'j'], Duration::from_millis(500)), 
+                              correct: vec!['j'], ..Trial::default()};
+            let block = Block{prelude: Prelude::Now, relax: Relax::Now, trials: vec![trial], ..Block::default()};
+            let mut session = Session::new(Experiment{blocks: vec![block], ..Experiment::default()}, 
+                                           Participant::default());
+            session.allowed_keys = Some(HashSet::from(['j']));
+            let (keys, input) = channel();
+            for _ in 0..3 {
+                keys.send(Input::Navi(NaviEvent::Forward)).unwrap();
+            }
+            std::thread::spawn(move || 
+                for key in ['f', 'j'] {
+                    sleep(Duration::from_millis(30));
+                    let _ = keys.send(Input::Key(key));
+                });
+            let obs = session.run(events_out, input, None).unwrap();
+            assert!(matches!(obs[0].response, crate::trial::Response::RTCorrect(rt, true) 
+                             if rt >= Duration::from_millis(50)));
+            let keys: Vec<Key> = obs[0].key_events.iter().map(|(key, _)| *key).collect();
+            assert_eq!(keys, ['j']);
+        }
+
+        #[test]
+        fn duplicate_block_names_do_not_validate() {
+            let exp = Experiment{blocks: vec![quick(Vec::new()), quick(Vec::new())], ..Experiment::default()};
+            let error = ValidationError::DuplicateBlockName("Block".into());
+            assert_eq!(exp.validate(), Err(error.clone()));
+            let (events_out, _events) = channel();
+            let aborted = Session::new(exp, Participant::default()).run(events_out, forwarded(), None).unwrap_err();
+            assert_eq!(aborted.error, YexError::Invalid(error));
+        }
 
+        #[test]
+        fn shared_pin_positions_do_not_validate() {
+            let mut exp = Experiment{random: true, ..Experiment::default()};
+            exp.pinned_positions.extend([(0, 1), (1, 1)]);
+            assert!(matches!(exp.validate(), Err(ValidationError::InconsistentPin(_, 1))));
+            assert_eq!(Experiment::default().validate(), Ok(()));
+        }
+
+        #[test]
+        fn pinned_blocks_stay_while_the_middle_varies() {
+            let exp = (0..6)
+                .fold(Experiment::builder(), |exp, i| exp.block(Block{name: format!("Block {}", i), ..Block::default()}))
+                .random(true).pin(0, 0).pin(5, 5)
+                .build();
+            let orders: HashSet<Vec<usize>> = (0..20)
+                .map(|seed| exp.block_order(&mut SessionRng::seed_from_u64(seed)).unwrap())
+                .collect();
+            assert!(orders.iter().all(|order| order[0] == 0 && order[5] == 5));
+            assert!(orders.iter().all(|order| order[1..5].iter().all(|block| (1..5).contains(block))));
+            assert!(orders.len() > 1);
+        }
+
+        #[test]
+        fn first_trial_of_every_block_is_flagged() {
+            let (events_out, _events) = channel();
+            let block = |name: &str| Block{name: name.into(), ..quick(vec![Trial::default(); 3])};
+            let exp = Experiment{blocks: vec![block("A"), block("B")], ..Experiment::default()};
+            let obs = Session::new(exp, Participant::default()).run(events_out, forwarded(), None).unwrap();
+            let first: Vec<bool> = obs.iter().map(|obs| obs.is_block_first).collect();
+            assert_eq!(first, [true, false, false, true, false, false]);
+        }
+
+        #[test]
+        fn seed_decides_the_order() {
+            let order = |seed: u64| {
+                let (events_out, _events) = channel();
+                let mut session = Session::new(words(), Participant::default());
+                session.seed = seed;
+                let obs = session.run(events_out, forwarded(), None).unwrap();
+                // the trial index leads back to the trial in the design
+                assert!(obs.iter().all(|obs| 
+                    obs.trial.condition == session.exp.blocks[obs.block].trials[obs.trial_index].condition));
+                obs.iter().map(|obs| (obs.block, obs.trial_index)).collect::<Vec<_>>()
+            };
+            assert_eq!(order(7), order(7));
+            assert_ne!(order(7), order(8));
+        }
+
+        #[test]
+        fn observations_stream_across_blocks() {
+            let (events_out, _events) = channel();
+            let mut session = Session::new(words(), Participant{id: 7, ..Participant::default()});
+            let stream = Arc::new(Mutex::new(Vec::new()));
+            session.stream_stdout_json = true;
+            session.json_out = JsonOut(stream.clone());
+            let obs = session.run(events_out, forwarded(), None).unwrap();
+            let streamed = String::from_utf8(stream.lock().unwrap().clone()).unwrap();
+            let expected: Vec<String> = obs.iter()
+                .map(|obs| {
+                    let mut line = Vec::new();
+                    write_json_line(obs, &mut line).unwrap();
+                    String::from_utf8(line).unwrap()})
+                .collect();
+            assert_eq!(obs.len(), 8);
+            assert_eq!(obs.iter().filter(|obs| obs.block == 1).count(), 4);
+            assert_eq!(streamed, expected.concat());
+        }
+
+        #[test]
+        fn genuine_run_verifies() {
+            let (events_out, _events) = channel();
+            let mut session = Session::new(words(), Participant{id: 7, ..Participant::default()});
+            session.run(events_out, forwarded(), None).unwrap();
+            assert_eq!(session.observations.len(), 8);
+            assert!(Session::verify_reproducible(&session));
+        }
+
+        #[test]
+        fn conditions_send_their_trigger_codes() {
+            let tagged = |condition: Option<&str>| Trial{condition: condition.map(Into::into), ..Trial::default()};
+            let block = quick(vec![tagged(Some("congruent")), tagged(Some("incongruent")), tagged(None), tagged(Some("neutral"))]);
+            let mut session = Session::new(Experiment{blocks: vec![block], ..Experiment::default()}, 
+                                           Participant::default());
+            session.trigger_codes = HashMap::from([("congruent".into(), 1), ("incongruent".into(), 2)]);
+            session.default_trigger = 9;
+            let (trigger_out, triggers) = channel();
+            session.trigger_out = Some(trigger_out);
+            let (events_out, _events) = channel();
+            session.run(events_out, forwarded(), None).unwrap();
+            assert_eq!(triggers.try_iter().collect::<Vec<u8>>(), [1, 2, 9, 9]);
+        }
+
+        #[test]
+        fn resumed_session_goes_through_its_states() {
+            let blocks = ["A", "B", "C"].map(|name| Block{name: name.into(), ..quick(vec![Trial::default(); 2])});
+            let exp = Experiment{blocks: blocks.to_vec(), ..Experiment::default()};
+            let mut session = Session::new(exp.clone(), Participant::default());
+            let (events_out, events) = channel();
+            let obs = session.run_from_block("B", events_out, forwarded(), None).unwrap();
+            assert_eq!(obs.iter().map(|obs| obs.block).collect::<Vec<_>>(), [1, 1, 2, 2]);
+            assert!(matches!(session.state, State::Goodbye));
+            let events: Vec<String> = events.try_iter()
+                .filter_map(|YexRecord(_, event)| match event {
+                    YexEvent::BlockSkipped(name) => Some(name),
+                    YexEvent::Session(state) => Some(crate::render::status(&YexEvent::Session(state))),
+                    _ => None})
+                .collect();
+            assert_eq!(events, ["A", "Session: Welcome", "Session: Consent", "Session: Demographics", 
+                                "Session: Blocks", "Session: Blocks", "Session: Goodbye"]);
+            // waiting for Forward in Welcome, until the watchdog
+            let mut session = Session::new(exp, Participant::default());
+            let (events_out, _events) = channel();
+            let (_keys, input) = channel();
+            let error = session.run_from_block("B", events_out, input, Some(Duration::from_millis(50))).unwrap_err();
+            assert_eq!(error.error, YexError::WatchdogTimeout);
+            let (events_out, _events) = channel();
+            let error = session.run_from_block("D", events_out, forwarded(), None).unwrap_err();
+            assert_eq!(error.error, YexError::BlockNotFound("D".into()));
+        }
+
+        #[test]
+        fn watchdog_aborts_a_stuck_run() {
+            let waiting = Trial{prelude: crate::trial::Prelude::Now, advance: Advance::Keys(vec!['f']), ..Trial::default()};
+            let block = Block{prelude: Prelude::Now, relax: Relax::Now, trials: vec![waiting; 3], ..Block::default()};
+            let mut session = Session::new(Experiment{blocks: vec![block], ..Experiment::default()}, 
+                                           Participant::default());
+            // through the states, then nobody presses a key
+            let (keys, input) = channel();
+            for _ in 0..3 {
+                keys.send(Input::Navi(NaviEvent::Forward)).unwrap();
+            }
+            let (events_out, _events) = channel();
+            let start = Instant::now();
+            let aborted = session.run(events_out, input, Some(Duration::from_millis(200))).unwrap_err();
+            let elapsed = start.elapsed();
+            assert_eq!(aborted.error, YexError::WatchdogTimeout);
+            assert!(elapsed >= Duration::from_millis(200) && elapsed < Duration::from_secs(2), "{:?}", elapsed);
+            assert!(matches!(session.state, State::Goodbye));
+            assert_eq!(session.observations, aborted.observations);
+        }
+
+        /// An eye tracker looking at the given spots, one after the other, 
+        /// CALIBRATION_SAMPLES times each, then nowhere
+        struct MockGaze(Vec<(f32, f32)>, usize);
+
+        impl GazeSource for MockGaze {
+            fn next_sample(&mut self, _: Instant) -> Option<GazeSample> {
+                let (x, y) = *self.0.get(self.1 / CALIBRATION_SAMPLES)?;
+                self.1 += 1;
+                Some(GazeSample{time: Instant::now(), x, y})
+            }
+        }
+
+        #[test]
+        fn calibration_measures_the_gaze_error() {
+            let targets = [Region{x: 0.2, y: 0.2, radius: 0.05}, Region{x: 0.8, y: 0.5, radius: 0.05}];
+            let session = Session::new(Experiment::default(), Participant::default());
+            let (events_out, events) = channel();
+            let result = session.calibrate(&targets, &mut MockGaze(vec![(0.23, 0.2), (0.8, 0.52)], 0), events_out);
+            assert!(result.success());
+            assert_eq!(result.points.iter().map(|point| point.samples).collect::<Vec<_>>(), 
+                       [CALIBRATION_SAMPLES; 2]);
+            assert!((result.points[0].error.unwrap() - 0.03).abs() < 1e-4);
+            assert!((result.points[1].error.unwrap() - 0.02).abs() < 1e-4);
+            let shown = events.try_iter()
+                .filter(|YexRecord(_, event)| matches!(event, YexEvent::CalibrationTarget(_)))
+                .count();
+            assert_eq!(shown, 2);
+            // off the second target, and no samples for it
+            let (events_out, _events) = channel();
+            let off = session.calibrate(&targets, &mut MockGaze(vec![(0.2, 0.2), (0.9, 0.5)], 0), events_out);
+            assert!(!off.success());
+            let (events_out, _events) = channel();
+            let lost = session.calibrate(&targets, &mut MockGaze(vec![(0.2, 0.2)], 0), events_out);
+            assert_eq!((lost.points[1].samples, lost.points[1].error), (0, None));
+            assert!(!lost.success());
+        }
 
+        #[test]
+        fn demo_summarizes_its_trials() {
+            let (events_out, _events) = channel();
+            let session = Arc::new(Mutex::new(Session::new(words(), Participant::default())));
+            let (obs, summary) = crate::demo(session, events_out, forwarded()).unwrap();
+            assert_eq!(obs.len(), 8);
+            assert_eq!(summary.summary, crate::output::summarize(&obs));
+            assert_eq!(summary.summary.trials, 8);
+            assert!(summary.duration > Duration::ZERO);
+            assert!(summary.to_string().starts_with("8 trials"));
+        }
+
+        #[test]
+        fn tampered_run_does_not_verify() {
+            let (events_out, _events) = channel();
+            let mut session = Session::new(words(), Participant{id: 7, ..Participant::default()});
+            session.run(events_out, forwarded(), None).unwrap();
+            session.observations.swap(0, 1);
+            assert!(!Session::verify_reproducible(&session));
+            session.observations.clear();
+            assert!(!Session::verify_reproducible(&session));
+        }
+    }
 }
 
 
 /// Block level
 
 pub mod block { 
-    use super::trial::{Trial, Observation};
-    use super::{Sender, Duration, Instant, sleep, Key, Text, YexRecord, YexEvent};
+    use super::trial::{Trial, Observation, Stimulus, Response, TrialHooks, StimulusHook};
+    use super::input::Responder;
+    use super::session::SessionRng;
+    use super::output::{YexError, Aborted, summarize, write_json_line};
+    use super::{Sender, Duration, Instant, sleep, Key, Text, YexRecord, YexEvent, Arc};
+    use std::sync::Mutex;
+    use std::io::Write;
+    use rand::Rng;
+    use rand::seq::SliceRandom;
 
     /// A Block is a sequences of Trials
     /// 
-    /// with a prelude and relax frame.
+    /// with a prelude and relax frame, and a name to refer to it.
     /// 
     /// + running through trials
     /// + sending block-level events
     /// 
+    /// For partial reinforcement, feedback_probability is the share 
+    /// of trials that get their feedback, drawn from the session RNG.
+    /// 
+    /// With auto_mask, every trial's target is followed by the mask 
+    /// stimulus after the SOA (backward masking).
+    /// 
+    /// The iti is a pause between trials, sampled from the session RNG.
+    /// 
+    /// An attention probe is asked at random intervals, between trials when it is due. 
+    /// The answers are collected in probe_responses, apart from the observations.
+    /// 
+    /// With a gated relax, relax_below records the branch the run has taken, 
+    /// true for the below branch.
+    /// 
+    /// Generated trials and the scoring hook see the last history_window 
+    /// observations, all of the block with None.
+    /// 
+    /// With random, the trials run in a shuffled order, 
+    /// drawn from the block RNG, see trial_order.
+    /// 
+    /// before_stimulus and after_stimulus are called with every trial, 
+    /// right before its onset and right after the stimulus clears, 
+    /// for external hardware like shutters.
+    /// 
+    /// Blocks are equal when they only differ in their id.
+    /// 
     #[derive(Clone, Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
     pub struct Block{
+        #[cfg_attr(feature = "serde", serde(skip))]
         pub id: Instant,
+        pub name: String,
         pub trials: Vec<Trial>,
         pub random: bool,
         pub prelude: Prelude,
         pub relax: Relax,
+        #[cfg_attr(feature = "serde", serde(skip))]
         pub state: State,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        pub make_next: Option<Generator>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::ms"))]
+        pub response_lockout: Duration,
+        pub feedback_probability: f32,
+        #[cfg_attr(feature = "serde", serde(with = "crate::ms::pair"))]
+        pub auto_mask: Option<(Stimulus, Duration)>,
+        pub iti: DurationDist,
+        pub attention_probe: Option<ProbeConfig>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        pub probe_responses: Vec<ProbeResponse>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        pub relax_below: Option<bool>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        pub scorer: Option<Scorer>,
+        pub history_window: Option<usize>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        pub before_stimulus: Option<Callback>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        pub after_stimulus: Option<Callback>,
     }
 
     
@@ -193,57 +1349,634 @@ pub mod block {
         fn default() -> Self {
             let trials = vec![Trial::default(); 3];
             Block{  id: Instant::now(),
-                    trials: trials, 
+                    name: "Block".into(),
+                    trials, 
                     random: false, 
                     prelude: Prelude::Blank(Duration::from_millis(1000)),
                     relax: Relax::Wait(Duration::from_millis(2000)),
                     state: State::Init,
+                    make_next: None,
+                    response_lockout: Duration::ZERO,
+                    feedback_probability: 1.,
+                    auto_mask: None,
+                    iti: DurationDist::Fixed(Duration::ZERO),
+                    attention_probe: None,
+                    probe_responses: Vec::new(),
+                    relax_below: None,
+                    scorer: None,
+                    history_window: None,
+                    before_stimulus: None,
+                    after_stimulus: None,
                 }
         }
     }
 
-    #[derive(Clone, PartialEq, Debug)]    
-    /// Block states
-    /// 
-    pub enum State {
-        Init,
-        Prelude(Prelude),
-        Trials(), // trial number
-        Relax
+    impl PartialEq for Block {
+        fn eq(&self, other: &Self) -> bool {
+            self.name == other.name
+                && self.trials == other.trials
+                && self.random == other.random
+                && self.prelude == other.prelude
+                && self.relax == other.relax
+                && self.state == other.state
+                && self.make_next == other.make_next
+                && self.response_lockout == other.response_lockout
+                && self.feedback_probability == other.feedback_probability
+                && self.auto_mask == other.auto_mask
+                && self.iti == other.iti
+                && self.attention_probe == other.attention_probe
+                && self.probe_responses == other.probe_responses
+                && self.relax_below == other.relax_below
+                && self.scorer == other.scorer
+                && self.history_window == other.history_window
+                && self.before_stimulus == other.before_stimulus
+                && self.after_stimulus == other.after_stimulus
+        }
     }
 
-    /// Preludes types for Blocks
+    /// Building a block
     /// 
-    #[derive(Clone, PartialEq, Debug)]
-    pub enum Prelude {
-        Now,
-        Blank(Duration),
-        Instruct(Duration, Text),
-        InstructKeys(Vec<Key>, Text)
-    }
-
-    /// Relax types for Blocks
-    ///
+    /// Unset fields keep their defaults, the id is taken when building starts. 
+    /// The trials accumulate in order, without any, the block has the default trials.
     #[derive(Clone, Debug)]
-    pub enum Relax {
-        Now,
-        Wait(Duration),
-        Keys(Vec<Key>),
-        KeysMaxWait(Vec<Key>, Duration)
+    pub struct BlockBuilder {
+        block: Block,
+        trials: Option<Vec<Trial>>,
     }
 
-    
     impl Block {
-    /// Run a block
-    /// 
-    /// runs through one block and its trials
-    /// returns a vector of Observations (Trial + Response)
+        pub fn builder() -> BlockBuilder {
+            BlockBuilder{block: Block::default(), trials: None}
+        }
+    }
+
+    impl BlockBuilder {
+        pub fn name(mut self, name: &str) -> Self {
+            self.block.name = name.into();
+            self
+        }
+
+        pub fn prelude(mut self, prelude: Prelude) -> Self {
+            self.block.prelude = prelude;
+            self
+        }
+
+        pub fn relax(mut self, relax: Relax) -> Self {
+            self.block.relax = relax;
+            self
+        }
+
+        pub fn random(mut self, random: bool) -> Self {
+            self.block.random = random;
+            self
+        }
+
+        pub fn iti(mut self, iti: DurationDist) -> Self {
+            self.block.iti = iti;
+            self
+        }
+
+        pub fn response_lockout(mut self, lockout: Duration) -> Self {
+            self.block.response_lockout = lockout;
+            self
+        }
+
+        pub fn feedback_probability(mut self, probability: f32) -> Self {
+            self.block.feedback_probability = probability;
+            self
+        }
+
+        pub fn auto_mask(mut self, mask: Stimulus, soa: Duration) -> Self {
+            self.block.auto_mask = Some((mask, soa));
+            self
+        }
+
+        pub fn attention_probe(mut self, probe: ProbeConfig) -> Self {
+            self.block.attention_probe = Some(probe);
+            self
+        }
+
+        pub fn generator(mut self, generator: Generator) -> Self {
+            self.block.make_next = Some(generator);
+            self
+        }
+
+        pub fn scorer(mut self, scorer: Scorer) -> Self {
+            self.block.scorer = Some(scorer);
+            self
+        }
+
+        pub fn history_window(mut self, window: usize) -> Self {
+            self.block.history_window = Some(window);
+            self
+        }
+
+        pub fn before_stimulus(mut self, hook: Callback) -> Self {
+            self.block.before_stimulus = Some(hook);
+            self
+        }
+
+        pub fn after_stimulus(mut self, hook: Callback) -> Self {
+            self.block.after_stimulus = Some(hook);
+            self
+        }
+
+        pub fn trial(mut self, trial: Trial) -> Self {
+            self.trials.get_or_insert_with(Vec::new).push(trial);
+            self
+        }
+
+        pub fn build(mut self) -> Block {
+            if let Some(trials) = self.trials {
+                self.block.trials = trials;
+            }
+            self.block
+        }
+    }
+
+    /// Attention probes, like "Were you on task? 1-5"
+    /// 
+    /// + interval is the time from one probe to the next
+    /// + keys are the possible answers, max_wait limits the answer
+    /// 
+    /// Probes do not interrupt a trial, they are checked between trials. 
+    /// So a probe that falls due during a trial comes after it.
+    #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ProbeConfig {
+        pub prompt: Text,
+        pub interval: DurationDist,
+        pub keys: Vec<Key>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::ms"))]
+        pub max_wait: Duration,
+    }
+
+    /// The answer to an attention probe
+    /// 
+    /// + at is the time of the probe from the start of the trials, 
+    ///   due is when it was scheduled, which can be a trial earlier
+    /// + response is the key and its RT, None when there was no answer
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub struct ProbeResponse {
+        pub at: Duration,
+        pub due: Duration,
+        pub response: Option<(Key, Duration)>,
+    }
+
+    /// Jittered durations
+    /// 
+    /// + Uniform between min and max
+    /// + Exponential with mean, min and max. The exponential starts at min, 
+    ///   draws beyond max are repeated, so the mean holds when max is 
+    ///   well above the mean.
+    /// + Normal with mean, sd, min and max, draws outside are repeated
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum DurationDist {
+        Fixed(#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration),
+        Uniform{#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] min: Duration, 
+                 #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] max: Duration},
+        Exponential{#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] mean: Duration, 
+                     #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] min: Duration, 
+                     #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] max: Duration},
+        Normal{#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] mean: Duration, 
+                #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] sd: Duration, 
+                #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] min: Duration, 
+                #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] max: Duration},
+    }
+
+    impl DurationDist {
+        /// Drawing a duration, reproducible with the session RNG
+        pub fn sample(&self, rng: &mut SessionRng) -> Duration {
+            // truncated distributions give up and clamp after that many draws
+            const MAX_DRAWS: usize = 1000;
+            let truncated = |min: Duration, max: Duration, rng: &mut SessionRng, 
+                             draw: &dyn Fn(&mut SessionRng) -> f64| {
+                for _ in 0..MAX_DRAWS {
+                    let secs = draw(rng);
+                    if secs >= min.as_secs_f64() && secs <= max.as_secs_f64() {
+                        return Duration::from_secs_f64(secs)
+                    }
+                }
+                Duration::from_secs_f64(draw(rng).max(0.)).clamp(min, max)
+            };
+            match *self {
+                DurationDist::Fixed(dur) => dur,
+                DurationDist::Uniform{min, max} => {
+                    if max <= min {return min}
+                    Duration::from_secs_f64(rng.gen_range(min.as_secs_f64()..=max.as_secs_f64()))
+                },
+                DurationDist::Exponential{mean, min, max} => {
+                    let scale = mean.saturating_sub(min).as_secs_f64();
+                    truncated(min, max, rng, &|rng| {
+                        let u: f64 = rng.gen();
+                        min.as_secs_f64() - scale * (1. - u).ln()
+                    })
+                },
+                DurationDist::Normal{mean, sd, min, max} => {
+                    truncated(min, max, rng, &|rng| {
+                        // Box-Muller
+                        let (u, v): (f64, f64) = (rng.gen(), rng.gen());
+                        let z = (-2. * (1. - u).ln()).sqrt() * (std::f64::consts::TAU * v).cos();
+                        mean.as_secs_f64() + sd.as_secs_f64() * z
+                    })
+                },
+            }
+        }
+
+        /// Expected duration, ignoring truncation
+        pub fn mean(&self) -> Duration {
+            match *self {
+                DurationDist::Fixed(dur) => dur,
+                DurationDist::Uniform{min, max} => (min + max.max(min)) / 2,
+                DurationDist::Exponential{mean, min, max} 
+                | DurationDist::Normal{mean, min, max, ..} => mean.clamp(min, max.max(min)),
+            }
+        }
+    }
+
+    /// Trial generator
+    /// 
+    /// For sequential-dependency and n-back designs, where a trial
+    /// depends on what happened before. When a block has a generator,
+    /// it runs n trials, each created by make_next from the
+    /// history of the block so far, see RunContext. The static trials
+    /// are not used then. Generators are equal when they share make_next.
+    /// 
+    #[derive(Clone)]
+    pub struct Generator {
+        pub n: usize,
+        pub make_next: Arc<MakeNext>,
+    }
+
+    pub type MakeNext = dyn Fn(&mut RunContext) -> Trial + Send + Sync;
+
+    impl std::fmt::Debug for Generator {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_struct("Generator").field("n", &self.n).finish_non_exhaustive()
+        }
+    }
+
+    impl PartialEq for Generator {
+        fn eq(&self, other: &Self) -> bool {
+            self.n == other.n && Arc::ptr_eq(&self.make_next, &other.make_next)
+        }
+    }
+
+    /// Scoring hook
+    /// 
+    /// replaces the scoring of a key press by the trial (correct key), 
+    /// e.g. for scoring that depends on previous responses. 
+    /// Gets the trial, the key, the RT and the history of the block.
+    /// Scorers are equal when they are the same function.
+    #[derive(Clone)]
+    pub struct Scorer(pub Arc<Score>);
+
+    pub type Score = dyn Fn(&Trial, Key, Duration, &mut RunContext) -> Response + Send + Sync;
+
+    impl std::fmt::Debug for Scorer {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_struct("Scorer").finish_non_exhaustive()
+        }
+    }
+
+    impl PartialEq for Scorer {
+        fn eq(&self, other: &Self) -> bool {
+            Arc::ptr_eq(&self.0, &other.0)
+        }
+    }
+
+    /// Callback around the stimulus, see before_stimulus. 
+    /// Callbacks are equal when they are the same function.
+    #[derive(Clone)]
+    pub struct Callback(pub Arc<StimulusHook>);
+
+    impl std::fmt::Debug for Callback {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_struct("Callback").finish_non_exhaustive()
+        }
+    }
+
+    impl PartialEq for Callback {
+        fn eq(&self, other: &Self) -> bool {
+            Arc::ptr_eq(&self.0, &other.0)
+        }
+    }
+
+    /// Where a block runs
+    /// 
+    /// The participant and experiment ids and the index of the block 
+    /// are stamped on every observation as it completes. 
+    /// Then it goes to json_out, as one line of JSON, see write_json_line. 
+    /// A streamed line cannot be taken back: when Back drops an observation, 
+    /// the trial is streamed again when it completes, and the later line counts.
+    #[derive(Clone, Debug)]
+    pub struct BlockContext {
+        pub participant: usize,
+        pub experiment: Arc<str>,
+        pub block: usize,
+        pub json_out: Option<JsonOut>,
+    }
+
+    impl Default for BlockContext {
+        fn default() -> Self {
+            BlockContext{participant: 0, experiment: "".into(), block: 0, json_out: None}
+        }
+    }
+
+    /// A stream of observations, e.g. stdout for piping into jq
+    #[derive(Clone)]
+    pub struct JsonOut(pub Arc<Mutex<dyn Write + Send>>);
+
+    impl JsonOut {
+        pub fn stdout() -> Self {
+            JsonOut(Arc::new(Mutex::new(std::io::stdout())))
+        }
+    }
+
+    impl std::fmt::Debug for JsonOut {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_struct("JsonOut").finish_non_exhaustive()
+        }
+    }
+
+    impl BlockContext {
+        pub fn stamp(&self, obs: &mut Observation) {
+            obs.participant = self.participant;
+            obs.experiment = self.experiment.clone();
+            obs.block = self.block;
+        }
+    }
+
+    /// What the hooks know about the run so far
+    /// 
+    /// + history are the last observations of the block, 
+    ///   as many as the block's history_window
+    /// + accuracy and mean_rt are running over the whole block
+    /// + rng is the block RNG, seeded from the session RNG before the blocks run, 
+    ///   so that hooks are reproducible per block, whatever runs before
+    pub struct RunContext<'a> {
+        pub history: &'a [Observation],
+        pub accuracy: Option<f32>,
+        pub mean_rt: Option<Duration>,
+        pub rng: &'a mut SessionRng,
+    }
+
+    impl<'a> RunContext<'a> {
+        pub fn new(observations: &'a [Observation], window: Option<usize>, rng: &'a mut SessionRng) -> Self {
+            let summary = summarize(observations);
+            let start = window.map_or(0, |window| observations.len().saturating_sub(window));
+            Self{history: &observations[start..], 
+                 accuracy: summary.accuracy, 
+                 mean_rt: summary.mean_rt, 
+                 rng}
+        }
+
+        pub fn last(&self) -> Option<&Observation> {
+            self.history.last()
+        }
+    }
+
+    #[derive(Clone, PartialEq, Debug)]    
+    /// Block states
+    /// 
+    pub enum State {
+        Init,
+        Prelude(Prelude),
+        Trials(), // trial number
+        Relax
+    }
+
+    /// Preludes types for Blocks
+    /// 
+    #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Prelude {
+        Now,
+        Blank(#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration),
+        Instruct(#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration, Text),
+        InstructKeys(Vec<Key>, Text)
+    }
+
+    /// Relax types for Blocks
+    ///
+    #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Relax {
+        Now,
+        Wait(#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration),
+        Keys(Vec<Key>),
+        KeysMaxWait(Vec<Key>, #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration),
+        /// relax below and relax at or above an accuracy threshold
+        PerformanceGated{threshold: f32, below: Box<Relax>, at_or_above: Box<Relax>},
+    }
+
+    impl Relax {
+        /// Choosing the relax by the accuracy of the block
+        /// 
+        /// Gated relax follows its branch, which may be gated again. 
+        /// Without accuracy (no correct answers), the at_or_above branch is taken.
+        /// Returns the relax to run and whether the below branch was taken 
+        /// at the first gate (None without gate).
+        pub fn resolve(&self, accuracy: Option<f32>) -> (Relax, Option<bool>) {
+            match self {
+                Relax::PerformanceGated{threshold, below, at_or_above} => {
+                    let is_below = accuracy.map_or(false, |accuracy| accuracy < *threshold);
+                    let branch = if is_below {below} else {at_or_above};
+                    (branch.resolve(accuracy).0, Some(is_below))
+                },
+                relax => (relax.clone(), None),
+            }
+        }
+
+        /// All durations, including both branches of gated relax
+        pub fn durations(&self) -> Vec<Duration> {
+            match self {
+                Relax::Wait(dur) | Relax::KeysMaxWait(_, dur) => vec![*dur],
+                Relax::Now | Relax::Keys(_) => Vec::new(),
+                Relax::PerformanceGated{below, at_or_above, ..} 
+                    => [below.durations(), at_or_above.durations()].concat(),
+            }
+        }
+
+        /// Estimated duration, the longer branch for gated relax
+        pub fn estimated_duration(&self) -> Duration {
+            match self {
+                Relax::Wait(dur) | Relax::KeysMaxWait(_, dur) => *dur,
+                Relax::Now | Relax::Keys(_) => Duration::ZERO,
+                Relax::PerformanceGated{below, at_or_above, ..} 
+                    => below.estimated_duration().max(at_or_above.estimated_duration()),
+            }
+        }
+    }
+
+    
+    impl Block {
+        /// Finding duplicate trials
+        /// 
+        /// returns all pairs of trial indices with the same stimulus and condition.
+        /// This is for auditing generated or merged designs, 
+        /// duplicates are not an error by themselves.
+        pub fn find_duplicates(&self) -> Vec<(usize, usize)> {
+            let mut pairs = Vec::new();
+            for (i, a) in self.trials.iter().enumerate() {
+                for (j, b) in self.trials.iter().enumerate().skip(i + 1) {
+                    if a.stimulus == b.stimulus && a.condition == b.condition {
+                        pairs.push((i, j));
+                    }
+                }
+            }
+            pairs
+        }
+
+        /// Balanced assignment of stimuli from a pool
+        /// 
+        /// creates per_condition trials for every condition, 
+        /// with stimuli drawn from the pool without replacement. 
+        /// The trials are otherwise default, tagged with their condition, 
+        /// and come ordered by condition.
+        /// Fails with InsufficientTrials if the pool has fewer items than trials are needed.
+        pub fn assign_from_pool<R: Rng>(pool: &[Stimulus], per_condition: usize, 
+                                        conditions: &[&str], rng: &mut R) 
+                -> Result<Vec<Trial>, YexError> {
+            Block::assign_balanced(pool, per_condition, per_condition, conditions, rng)
+        }
+
+        /// Balanced assignment with a minimum per condition
+        /// 
+        /// like assign_from_pool, but a pool too small for per_condition 
+        /// trials everywhere is dealt out over the conditions in turns. 
+        /// Fails if a condition ends up with fewer than min_per_condition trials, 
+        /// instead of running an underpowered cell.
+        pub fn assign_balanced<R: Rng>(pool: &[Stimulus], per_condition: usize, min_per_condition: usize,
+                                       conditions: &[&str], rng: &mut R) 
+                -> Result<Vec<Trial>, YexError> {
+            let mut draw: Vec<usize> = (0..pool.len()).collect();
+            draw.shuffle(rng);
+            let mut draw = draw.into_iter();
+            let mut cells: Vec<Vec<Trial>> = vec![Vec::new(); conditions.len()];
+            'dealing: for _ in 0..per_condition {
+                for (cell, condition) in cells.iter_mut().zip(conditions) {
+                    let Some(item) = draw.next() else {break 'dealing};
+                    cell.push(Trial{stimulus: pool[item].clone(),
+                                    condition: Some(condition.to_string()),
+                                    ..Trial::default()});
+                }
+            }
+            let trials: Vec<Trial> = cells.into_iter().flatten().collect();
+            Block::check_min_per_condition(&trials, conditions, min_per_condition)?;
+            Ok(trials)
+        }
+
+        /// Every condition has at least min trials
+        pub fn check_min_per_condition(trials: &[Trial], conditions: &[&str], min: usize) 
+                -> Result<(), YexError> {
+            for condition in conditions {
+                let have = trials.iter()
+                    .filter(|trial| trial.condition.as_deref() == Some(*condition))
+                    .count();
+                if have < min {
+                    return Err(YexError::InsufficientTrials{condition: condition.to_string(), have, need: min})
+                }
+            }
+            Ok(())
+        }
+
+        /// The order of trials as indices
+        /// 
+        /// shuffled with random. This is the first draw of a block run, 
+        /// so a block RNG with the same seed gives the same order.
+        pub fn trial_order(&self, rng: &mut SessionRng) -> Vec<usize> {
+            let mut order: Vec<usize> = (0..self.trials.len()).collect();
+            if self.random {
+                order.shuffle(rng);
+            }
+            order
+        }
+
+        /// The trials in the order they are run with this RNG, 
+        /// None for generated trials
+        pub fn plan(&self, rng: &mut SessionRng) -> Option<Vec<Trial>> {
+            if self.make_next.is_some() {return None}
+            let trials = self.trial_order(rng).into_iter()
+                .map(|index| self.trials[index].clone())
+                .map(|mut trial| {
+                    if self.auto_mask.is_some() {
+                        trial.mask = self.auto_mask.clone();
+                    }
+                    trial
+                })
+                .collect();
+            Some(trials)
+        }
+
+        /// Loading the stimuli of all trials and the auto mask
+        /// 
+        /// before the block runs, so that text is rasterized and images are read 
+        /// once, and not within the timing of every trial. 
+        /// Generated trials are loaded when they are generated.
+        pub fn prepare(&mut self) -> Result<(), YexError> {
+            for trial in self.trials.iter_mut() {
+                trial.prepare()?;
+            }
+            if let Some((mask, _)) = self.auto_mask.as_mut() {
+                mask.load()?;
+            }
+            Ok(())
+        }
+
+        /// Configured durations of the block itself, 
+        /// for validation
+        pub fn durations(&self) -> Vec<(&'static str, Duration)> {
+            let mut durations = Vec::new();
+            if let Prelude::Blank(dur) | Prelude::Instruct(dur, _) = self.prelude {
+                durations.push(("prelude", dur));
+            }
+            durations.extend(self.relax.durations().into_iter().map(|dur| ("relax", dur)));
+            match self.iti {
+                DurationDist::Fixed(dur) => durations.push(("iti", dur)),
+                DurationDist::Uniform{min, ..} 
+                | DurationDist::Exponential{min, ..} 
+                | DurationDist::Normal{min, ..} => durations.push(("iti", min)),
+            }
+            durations.push(("response lockout", self.response_lockout));
+            durations
+        }
+
+        /// Estimated duration
+        /// 
+        /// Waits for keys without a time limit count as zero, 
+        /// limited waits with their maximum. Generated trials are not counted. 
+        pub fn estimated_duration(&self) -> Duration {
+            let prelude = match self.prelude {
+                Prelude::Blank(dur) | Prelude::Instruct(dur, _) => dur,
+                Prelude::Now | Prelude::InstructKeys(_, _) => Duration::ZERO,
+            };
+            let relax = self.relax.estimated_duration();
+            let trials: Duration = self.trials.iter().map(|trial| trial.estimated_duration()).sum();
+            let itis = self.iti.mean() * self.trials.len().saturating_sub(1) as u32;
+            prelude + trials + itis + relax
+        }
+
+    /// Run a block
+    /// 
+    /// runs through one block and its trials
+    /// returns a vector of Observations (Trial + Response)
     /// 1. initialize the output vector
     /// 2. do the prelude
-    /// 3. cycle through trials and 
+    /// 3. cycle through trials (or generate them) and 
     /// 4. Run the relax period
     /// 
-        pub fn run(&mut self, events_out: Sender<YexRecord>) -> Option<Vec<Observation>> {
+    /// Forward skips the prelude and relax, Back in a trial 
+    /// presents the previous trial again, whose observation is dropped 
+    /// (but stays in json_out, see BlockContext).
+    /// Generated trials are generated again.
+    /// 
+    /// The observations carry the ids of the context.
+    /// 
+        pub fn run(&mut self, context: &BlockContext, events_out: Sender<YexRecord>, 
+                   responder: &mut Responder, rng: &mut SessionRng) 
+                -> Result<Vec<Observation>, Aborted> {
             events_out.send(YexEvent::Block(self.state.clone()).into()).unwrap();
             let mut out: Vec<Observation> = Vec::new();
             self.state = State::Prelude(self.prelude.clone());
@@ -251,172 +1984,2480 @@ pub mod block {
             match self.prelude.clone() {
                 Prelude::Now
                     => {},
-                Prelude::Blank(dur)
-                    => {sleep(dur)},
-                Prelude::Instruct(dur, _) 
-                    => {sleep(dur);},
-                _   => todo!(),
+                Prelude::Blank(dur) | Prelude::Instruct(dur, _)
+                    => {responder.pause(&[], Some(Instant::now() + dur))
+                            .map_err(|error| Aborted{error, observations: Vec::new()})?;},
+                Prelude::InstructKeys(keys, _)
+                    => {responder.pause(&keys, None)
+                            .map_err(|error| Aborted{error, observations: Vec::new()})?;},
             }
             self.state = State::Trials();
             events_out.send(YexEvent::Block(self.state.clone()).into()).unwrap();
-            for trial in self.trials.clone(){
+            responder.lockout = self.response_lockout;
+            let order: Vec<usize> = match &self.make_next {
+                Some(gen) => (0..gen.n).collect(),
+                None => self.trial_order(rng),
+            };
+            let trials_start = Instant::now();
+            let mut next_probe = self.attention_probe.as_ref()
+                .map(|probe| trials_start + probe.interval.sample(rng));
+            let mut position = 0;
+            while let Some(&index) = order.get(position) {
+                if position > 0 {
+                    sleep(self.iti.sample(rng));
+                }
+                // completing the flips of the last trial, which do not go into json_out
+                if let Some(last) = out.last_mut() {
+                    last.flip_times.extend(responder.late_flips());
+                }
+                let mut trial = match &self.make_next {
+                    Some(gen) => (gen.make_next)(&mut RunContext::new(&out, self.history_window, rng)),
+                    None => self.trials[index].clone(),
+                };
+                if self.auto_mask.is_some() {
+                    trial.mask = self.auto_mask.clone();
+                }
+                // partial reinforcement
+                if trial.feedback.is_some() && self.feedback_probability < 1.
+                        && rng.gen::<f32>() >= self.feedback_probability {
+                    trial.feedback = None;
+                }
                 // making an observation by running a trial
-                let obs 
-                    = trial.clone().run(events_out.clone());
+                let window = self.history_window;
+                let scorer = self.scorer.clone();
+                let mut score = |trial: &Trial, key: Key, rt: Duration| match &scorer {
+                    Some(Scorer(score)) => score(trial, key, rt, &mut RunContext::new(&out, window, rng)),
+                    None => trial.score(key, rt),
+                };
+                let hooks = TrialHooks{
+                    score: &mut score,
+                    before_stimulus: self.before_stimulus.as_ref().map(|Callback(hook)| hook.as_ref()),
+                    after_stimulus: self.after_stimulus.as_ref().map(|Callback(hook)| hook.as_ref())};
+                let obs = trial.run_with(events_out.clone(), responder, hooks);
                 match obs {
-                    None => {},
-                    Some(obs) => {
+                    // going back, or again at the first trial
+                    Err(Aborted{error: YexError::NavigateBack, ..}) => {
+                        if out.pop().is_some() {
+                            position -= 1;
+                            // of the dropped observation
+                            responder.late_flips();
+                        }
+                        continue
+                    },
+                    Err(mut aborted) => {
+                        if let Some(obs) = aborted.observations.first_mut() {
+                            obs.is_block_first = out.is_empty();
+                            obs.trial_index = index;
+                            context.stamp(obs);
+                        }
+                        return Err(aborted.after(out))},
+                    Ok(mut obs) => {
                         // collecting new observation
-                        out.push(obs);}
+                        obs.is_block_first = out.is_empty();
+                        obs.trial_index = index;
+                        context.stamp(&mut obs);
+                        if let Some(JsonOut(json_out)) = &context.json_out {
+                            // a broken pipe does not stop the experiment
+                            let _ = write_json_line(&obs, &mut *json_out.lock().unwrap());
+                        }
+                        out.push(obs);
+                        position += 1;}
+                }
+                // attention probe, when it is due
+                if let (Some(probe), Some(due)) = (self.attention_probe.clone(), next_probe) {
+                    if Instant::now() >= due {
+                        let onset = Instant::now();
+                        events_out.send(YexEvent::Probe(probe.prompt.clone()).into()).unwrap();
+                        responder.start_window();
+                        let pressed = responder.wait_key(&probe.keys, Some(onset + probe.max_wait))
+                            .or_else(|error| match error {
+                                YexError::NavigateBack => Ok(None),
+                                error => Err(error),
+                            })
+                            .map_err(|error| Aborted{error, observations: Vec::new()}.after(out.clone()))?;
+                        self.probe_responses.push(ProbeResponse{
+                            at: onset - trials_start, 
+                            due: due - trials_start,
+                            response: pressed.map(|(key, time)| (key, time - onset))});
+                        next_probe = Some(Instant::now() + probe.interval.sample(rng));
+                    }
                 }
             }
 
             self.state = State::Relax;
             events_out.send(YexEvent::Block(self.state.clone()).into()).unwrap();
-            match self.relax {
+            if let Some(last) = out.last_mut() {
+                last.flip_times.extend(responder.late_flips());
+            }
+            let accuracy = summarize(&out).accuracy;
+            let (relax, below) = self.relax.resolve(accuracy);
+            self.relax_below = below;
+            if let Some(below) = below {
+                events_out.send(YexEvent::RelaxGated(accuracy, below).into()).unwrap();
+            }
+            match relax {
                 Relax::Now 
                     => {}, // do nothing is not the same as not implemented
                 Relax::Wait(dur) 
-                    => {sleep(dur);},
-                _   => {todo!();}
+                    => {responder.pause(&[], Some(Instant::now() + dur))
+                            .map_err(|error| Aborted{error, observations: Vec::new()}.after(out.clone()))?;},
+                Relax::Keys(keys)
+                    => {responder.pause(&keys, None)
+                            .map_err(|error| Aborted{error, observations: Vec::new()}.after(out.clone()))?;},
+                Relax::KeysMaxWait(keys, dur)
+                    => {responder.pause(&keys, Some(Instant::now() + dur))
+                            .map_err(|error| Aborted{error, observations: Vec::new()}.after(out.clone()))?;},
+                // resolved above
+                Relax::PerformanceGated{..} => {},
+            }
+            Ok(out)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{channel, Input, NaviEvent};
+        use crate::testing::{closed, typing};
+        use rand::SeedableRng;
+
+        #[test]
+        fn relax_wait_takes_its_time() {
+            let (events_out, _events) = channel();
+            let mut block = Block{prelude: Prelude::Now, trials: Vec::new(), 
+                                  relax: Relax::Wait(Duration::from_millis(200)), ..Block::default()};
+            let mut rng = SessionRng::seed_from_u64(0);
+            let start = Instant::now();
+            block.run(&BlockContext::default(), events_out, &mut closed(), &mut rng).unwrap();
+            let elapsed = start.elapsed();
+            assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+        }
+
+        #[test]
+        fn default_block_runs_without_input() {
+            let (events_out, _events) = channel();
+            let start = Instant::now();
+            let obs = Block::default().run(&BlockContext::default(), events_out, &mut closed(), 
+                                           &mut SessionRng::seed_from_u64(0)).unwrap();
+            assert_eq!(obs.len(), 3);
+            // prelude, three trials and relax take their time
+            assert!(start.elapsed() >= Duration::from_millis(4500), "{:?}", start.elapsed());
+        }
+
+        #[test]
+        fn keys_in_the_lockout_are_ignored() {
+            let (events_out, _events) = channel();
+            let trial = Trial{prelude: crate::trial::Prelude::Now,
+                              advance: crate::trial::Advance::KeysMaxWait(vec!['f', 'j'], Duration::from_millis(500)),
+                              correct: vec!['j'],
+                              ..Trial::default()};
+            let mut block = Block{prelude: Prelude::Now, relax: Relax::Now, trials: vec![trial],
+                                  response_lockout: Duration::from_millis(100), ..Block::default()};
+            let mut responder = typing(crate::testing::keys("fj"), Duration::from_millis(75));
+            let obs = block.run(&BlockContext::default(), events_out, &mut responder, 
+                                &mut SessionRng::seed_from_u64(0)).unwrap();
+            let locked: Vec<Key> = obs[0].locked_out.iter().map(|(key, _)| *key).collect();
+            assert_eq!(locked, ['f']);
+            assert!(matches!(obs[0].response, crate::trial::Response::RTCorrect(rt, true) 
+                             if rt >= Duration::from_millis(100)));
+        }
+
+        #[test]
+        fn feedback_follows_its_probability() {
+            let shown = |seed: u64| {
+                let (events_out, _events) = channel();
+                let trial = Trial{prelude: crate::trial::Prelude::Now, 
+                                  advance: crate::trial::Advance::Wait(Duration::ZERO),
+                                  feedback: Some((crate::trial::FeedbackMode::ThankYou, Duration::ZERO)),
+                                  ..Trial::default()};
+                let mut block = Block{prelude: Prelude::Now, relax: Relax::Now, trials: vec![trial; 200],
+                                      feedback_probability: 0.3, ..Block::default()};
+                block.run(&BlockContext::default(), events_out, &mut closed(), &mut SessionRng::seed_from_u64(seed))
+                    .unwrap().iter().map(|obs| obs.feedback.is_some()).collect::<Vec<bool>>()
+            };
+            let first = shown(1);
+            let frequency = first.iter().filter(|shown| **shown).count() as f32 / first.len() as f32;
+            assert!((0.2..0.4).contains(&frequency), "{frequency}");
+            assert_eq!(shown(1), first);
+            assert_ne!(shown(2), first);
+        }
+
+        #[test]
+        fn back_presents_the_previous_trial_again() {
+            let (events_out, events) = channel();
+            let trial = |condition: &str| Trial{prelude: crate::trial::Prelude::Now, 
+                                                advance: crate::trial::Advance::Keys(vec!['a']), 
+                                                condition: Some(condition.into()),
+                                                ..Trial::default()};
+            let mut block = Block{prelude: Prelude::Now, relax: Relax::Now, trials: vec![trial("1"), trial("2")], 
+                                  ..Block::default()};
+            let stream = Arc::new(Mutex::new(Vec::new()));
+            let context = BlockContext{json_out: Some(JsonOut(stream.clone())), ..BlockContext::default()};
+            // Back in the second trial, then both trials again
+            let inputs = vec![Input::Key('a'), Input::Navi(NaviEvent::Back), Input::Key('a'), Input::Key('a')];
+            let obs = block.run(&context, events_out, &mut typing(inputs, Duration::from_millis(30)), 
+                                &mut SessionRng::seed_from_u64(0)).unwrap();
+            let presented: Vec<String> = events.try_iter()
+                .filter_map(|YexRecord(_, event)| match event {
+                    YexEvent::Trial(crate::trial::State::Present(_, condition)) => condition,
+                    _ => None})
+                .collect();
+            assert_eq!(presented, ["1", "2", "1", "2"]);
+            assert_eq!(obs.iter().map(|obs| obs.trial_index).collect::<Vec<_>>(), [0, 1]);
+            // the dropped observation was already streamed
+            assert_eq!(String::from_utf8(stream.lock().unwrap().clone()).unwrap().lines().count(), 3);
+        }
+
+        #[test]
+        fn duplicates_come_in_pairs() {
+            let trial = |ms, condition: &str| Trial{stimulus: Stimulus::Blank(Duration::from_millis(ms)), 
+                                                   condition: Some(condition.into()),
+                                                   ..Trial::default()};
+            let block = Block{trials: vec![trial(1, "a"), trial(2, "a"), trial(1, "a"), trial(1, "b"), trial(1, "a")],
+                              ..Block::default()};
+            assert_eq!(block.find_duplicates(), vec![(0, 2), (0, 4), (2, 4)]);
+            let block = Block{trials: vec![trial(1, "a"), trial(2, "a"), trial(1, "b")], ..Block::default()};
+            assert!(block.find_duplicates().is_empty());
+        }
+
+        #[test]
+        fn auto_mask_follows_every_trial() {
+            let (events_out, _events) = channel();
+            let soa = Duration::from_millis(50);
+            let trial = Trial{prelude: crate::trial::Prelude::Now, 
+                              advance: crate::trial::Advance::Wait(Duration::from_millis(100)), 
+                              ..Trial::default()};
+            let mut block = Block{prelude: Prelude::Now, relax: Relax::Now, trials: vec![trial; 3], 
+                                  auto_mask: Some((Stimulus::Blank(Duration::from_millis(10)), soa)),
+                                  ..Block::default()};
+            let obs = block.run(&BlockContext::default(), events_out, &mut closed(), &mut SessionRng::seed_from_u64(0)).unwrap();
+            assert_eq!(obs.len(), 3);
+            for obs in obs {
+                let onset = obs.mask_onset.unwrap();
+                // at the SOA, within the response window
+                assert!(onset >= soa && onset < Duration::from_millis(100), "{:?}", onset);
             }
-            Some(out)
+        }
+
+        #[test]
+        fn probes_are_kept_apart_from_the_observations() {
+            let probed = |interval: Duration| {
+                let (events_out, events) = channel();
+                let trial = Trial{prelude: crate::trial::Prelude::Now, 
+                                  advance: crate::trial::Advance::Wait(Duration::from_millis(5)), 
+                                  ..Trial::default()};
+                let probe = ProbeConfig{prompt: "On task? 1-5".into(), interval: DurationDist::Fixed(interval), 
+                                        keys: vec!['1', '2', '3', '4', '5'], max_wait: Duration::from_millis(10)};
+                let mut block = Block{prelude: Prelude::Now, relax: Relax::Now, trials: vec![trial; 4], 
+                                      attention_probe: Some(probe), ..Block::default()};
+                // the input stays open, as probes wait for keys
+                let (_keys, input) = channel();
+                let obs = block.run(&BlockContext::default(), events_out, &mut Responder::new(input), 
+                                    &mut SessionRng::seed_from_u64(0)).unwrap();
+                let prompts = events.try_iter()
+                    .filter(|YexRecord(_, event)| matches!(event, YexEvent::Probe(_)))
+                    .count();
+                (obs.len(), prompts, block.probe_responses)
+            };
+            // due after every trial
+            let (trials, prompts, responses) = probed(Duration::ZERO);
+            assert_eq!((trials, prompts, responses.len()), (4, 4, 4));
+            assert!(responses.iter().all(|probe| probe.response.is_none()));
+            assert!(responses.windows(2).all(|pair| pair[0].at < pair[1].at));
+            // shown after the trial in which they fell due
+            assert_eq!(responses[0].due, Duration::ZERO);
+            assert!(responses.iter().all(|probe| probe.at >= probe.due + Duration::from_millis(5)));
+            let (trials, prompts, responses) = probed(Duration::from_secs(10));
+            assert_eq!((trials, prompts, responses.len()), (4, 0, 0));
+        }
+
+        #[test]
+        fn probes_have_no_lockout() {
+            let (events_out, _events) = channel();
+            let trial = Trial{prelude: crate::trial::Prelude::Now, 
+                              advance: crate::trial::Advance::Keys(vec!['a']), ..Trial::default()};
+            let probe = ProbeConfig{prompt: "On task? 1-5".into(), interval: DurationDist::Fixed(Duration::ZERO), 
+                                    keys: vec!['1'], max_wait: Duration::from_millis(300)};
+            let mut block = Block{prelude: Prelude::Now, relax: Relax::Now, trials: vec![trial], 
+                                  response_lockout: Duration::from_millis(100),
+                                  attention_probe: Some(probe), ..Block::default()};
+            let (keys, input) = channel();
+            std::thread::spawn(move || {
+                // after the lockout of the trial, but within one from the probe
+                for (key, gap) in [('a', 150), ('1', 30)] {
+                    sleep(Duration::from_millis(gap));
+                    keys.send(Input::Key(key)).unwrap();
+                }
+                sleep(Duration::from_millis(400));
+            });
+            block.run(&BlockContext::default(), events_out, &mut Responder::new(input), 
+                      &mut SessionRng::seed_from_u64(0)).unwrap();
+            assert!(matches!(block.probe_responses[0].response, Some(('1', rt)) if rt < Duration::from_millis(100)));
+        }
+
+        #[test]
+        fn pool_items_are_used_once() {
+            let pool: Vec<Stimulus> = (1..=6).map(|ms| Stimulus::Blank(Duration::from_millis(ms))).collect();
+            let mut rng = SessionRng::seed_from_u64(0);
+            let trials = Block::assign_from_pool(&pool, 3, &["a", "b"], &mut rng).unwrap();
+            let conditions: Vec<&str> = trials.iter().map(|trial| trial.condition.as_deref().unwrap()).collect();
+            assert_eq!(conditions, ["a", "a", "a", "b", "b", "b"]);
+            let mut used: Vec<Duration> = trials.iter()
+                .map(|trial| match trial.stimulus {
+                    Stimulus::Blank(dur) => dur,
+                    _ => unreachable!()})
+                .collect();
+            used.sort();
+            let all: Vec<Duration> = (1..=6).map(Duration::from_millis).collect();
+            assert_eq!(used, all);
+        }
+
+        #[test]
+        fn completed_observations_are_streamed() {
+            let (events_out, _events) = channel();
+            let trial = Trial{advance: crate::trial::Advance::Wait(Duration::from_millis(1)), ..Trial::default()};
+            let mut block = Block{prelude: Prelude::Now, relax: Relax::Now, trials: vec![trial; 3], ..Block::default()};
+            let stream = Arc::new(Mutex::new(Vec::new()));
+            let context = BlockContext{participant: 7, block: 2, json_out: Some(JsonOut(stream.clone())), 
+                                       ..BlockContext::default()};
+            let obs = block.run(&context, events_out, &mut closed(), &mut SessionRng::seed_from_u64(0)).unwrap();
+            let stream = String::from_utf8(stream.lock().unwrap().clone()).unwrap();
+            let lines: Vec<&str> = stream.lines().collect();
+            assert_eq!(lines.len(), obs.len());
+            assert!(lines.iter().all(|line| line.starts_with('{') && line.ends_with('}')));
+            assert!(lines.iter().all(|line| line.contains(r#""participant": 7"#) && line.contains(r#""block": 2"#)), 
+                    "{stream}");
+        }
+
+        #[test]
+        fn block_builder_accumulates_trials_and_hooks() {
+            assert_eq!(Block::builder().build(), Block::default());
+            let trial = |condition: &str| Trial{condition: Some(condition.into()), ..Trial::default()};
+            let hook = Callback(Arc::new(|_: &Trial| {}));
+            let block = Block::builder().trial(trial("a")).trial(trial("b")).trial(trial("c"))
+                .before_stimulus(hook.clone()).after_stimulus(hook.clone())
+                .build();
+            assert_eq!(block.trials, vec![trial("a"), trial("b"), trial("c")]);
+            assert_eq!(block.before_stimulus, Some(hook.clone()));
+            assert_eq!(block.after_stimulus, Some(hook));
+            assert_ne!(block, Block::default());
+        }
+
+        /// 1-back: m when the letter is the same as before, n otherwise
+        fn one_back(letters: &'static str) -> Block {
+            let make_next = move |context: &mut RunContext| {
+                let letter = letters.chars().nth(context.history.len()).unwrap();
+                let before = context.last().and_then(|obs| obs.trial.condition.clone());
+                let matches = before == Some(letter.to_string());
+                Trial{prelude: crate::trial::Prelude::Now,
+                      advance: crate::trial::Advance::KeysMaxWait(vec!['m', 'n'], Duration::from_millis(500)),
+                      correct: vec![if matches {'m'} else {'n'}],
+                      condition: Some(letter.to_string()),
+                      ..Trial::default()}
+            };
+            Block{prelude: Prelude::Now, relax: Relax::Now, 
+                  make_next: Some(Generator{n: letters.len(), make_next: Arc::new(make_next)}),
+                  ..Block::default()}
+        }
+
+        #[test]
+        fn low_accuracy_gets_the_below_relax() {
+            let (events_out, _events) = channel();
+            let mut block = Block{relax: Relax::PerformanceGated{threshold: 0.5, 
+                                                                 below: Box::new(Relax::Wait(Duration::from_millis(150))), 
+                                                                 at_or_above: Box::new(Relax::Now)},
+                                  ..one_back("AB")};
+            let mut responder = typing(crate::testing::keys("mm"), Duration::from_millis(30));
+            let start = Instant::now();
+            block.run(&BlockContext::default(), events_out, &mut responder, &mut SessionRng::seed_from_u64(0)).unwrap();
+            assert_eq!(block.relax_below, Some(true));
+            assert!(start.elapsed() >= Duration::from_millis(210), "{:?}", start.elapsed());
+            assert!(matches!(block.relax.resolve(Some(0.5)), (Relax::Now, Some(false))));
+            assert!(matches!(block.relax.resolve(None), (Relax::Now, Some(false))));
+        }
+
+        #[test]
+        fn scorer_sees_the_last_observation() {
+            // alternation: correct when the key differs from the one before
+            let alternate = |_: &Trial, key: Key, rt: Duration, context: &mut RunContext| {
+                let before = context.last().and_then(|obs| obs.key_events.last()).map(|(key, _)| *key);
+                Response::RTCorrect(rt, before != Some(key))
+            };
+            let trial = Trial{prelude: crate::trial::Prelude::Now, 
+                              advance: crate::trial::Advance::KeysMaxWait(vec!['a', 'b'], Duration::from_millis(500)), 
+                              ..Trial::default()};
+            let mut block = Block{prelude: Prelude::Now, relax: Relax::Now, trials: vec![trial; 4], 
+                                  scorer: Some(Scorer(Arc::new(alternate))), history_window: Some(1),
+                                  ..Block::default()};
+            let (events_out, _events) = channel();
+            let mut responder = typing(crate::testing::keys("abba"), Duration::from_millis(30));
+            let obs = block.run(&BlockContext::default(), events_out, &mut responder, &mut SessionRng::seed_from_u64(0)).unwrap();
+            let scored: Vec<Option<bool>> = obs.iter().map(|obs| obs.response.correct()).collect();
+            assert_eq!(scored, [true, true, false, true].map(Some));
+        }
+
+        #[test]
+        fn exponential_samples_hold_the_mean() {
+            let (mean, min, max) = (Duration::from_secs(3), Duration::from_secs(1), Duration::from_secs(20));
+            let iti = DurationDist::Exponential{mean, min, max};
+            let mut rng = SessionRng::seed_from_u64(0);
+            let draws: Vec<Duration> = (0..10_000).map(|_| iti.sample(&mut rng)).collect();
+            assert!(draws.iter().all(|draw| (min..=max).contains(draw)));
+            let sampled = draws.iter().sum::<Duration>() / draws.len() as u32;
+            assert!(sampled.as_secs_f64() > 2.9 && sampled.as_secs_f64() < 3.1, "{sampled:?}");
+            assert_eq!(iti.mean(), mean);
+        }
+
+        #[test]
+        fn one_back_is_scored_by_history() {
+            let (events_out, _events) = channel();
+            let mut responder = typing(crate::testing::keys("nmnmnn"), Duration::from_millis(30));
+            let obs = one_back("AABBBA").run(&BlockContext::default(), events_out, &mut responder, &mut SessionRng::seed_from_u64(0)).unwrap();
+            let correct: Vec<Key> = obs.iter().map(|obs| obs.trial.correct[0]).collect();
+            assert_eq!(correct, vec!['n', 'm', 'n', 'm', 'm', 'n']);
+            let scored: Vec<Option<bool>> = obs.iter()
+                .map(|obs| match obs.response {
+                    crate::trial::Response::RTCorrect(_, correct) => Some(correct),
+                    _ => None})
+                .collect();
+            assert_eq!(scored, [true, true, true, true, false, true].map(Some));
+        }
+
+        #[test]
+        fn small_pool_has_insufficient_trials() {
+            let pool: Vec<Stimulus> = (1..=5).map(|ms| Stimulus::Blank(Duration::from_millis(ms))).collect();
+            let mut rng = SessionRng::seed_from_u64(0);
+            let trials = Block::assign_from_pool(&pool, 2, &["a", "b"], &mut rng).unwrap();
+            assert_eq!(trials.len(), 4);
+            let error = Block::assign_from_pool(&pool, 3, &["a", "b"], &mut rng).unwrap_err();
+            assert_eq!(error, YexError::InsufficientTrials{condition: "b".into(), have: 2, need: 3});
+            let error = Block::assign_balanced(&pool, 3, 3, &["a", "b"], &mut rng).unwrap_err();
+            assert_eq!(error, YexError::InsufficientTrials{condition: "b".into(), have: 2, need: 3});
+            assert_eq!(Block::assign_balanced(&pool, 3, 2, &["a", "b"], &mut rng).unwrap().len(), 5);
+        }
+
+        #[test]
+        #[cfg(feature = "embedded_font")]
+        fn prepare_rasterizes_once() {
+            use crate::Language;
+            let stim = Stimulus::text(Duration::ZERO, "GREEN", 14, [0, 255, 0], Language::Eng).unwrap();
+            let mut block = Block{trials: vec![Trial{stimulus: stim, ..Trial::default()}], ..Block::default()};
+            block.prepare().unwrap();
+            let Stimulus::Text(_, _, _, _, _, Some(raster)) = &block.trials[0].stimulus 
+                else {panic!("not rasterized")};
+            // presenting a trial works on a clone, which keeps the raster
+            let mut trial = block.trials[0].clone();
+            trial.prepare().unwrap();
+            let Stimulus::Text(_, _, _, _, _, Some(again)) = &trial.stimulus else {panic!("raster lost")};
+            assert!(Arc::ptr_eq(raster, again));
+        }
+    }
+}
+
+
+/// Trial-level
+/// 
+
+pub mod trial { 
+    use crate::output::{YexRecord, Aborted, YexError};
+    use crate::input::{Responder, GazeSample};
+
+    use super::{Duration, Instant, sleep, Key, Text, Language, Sender, YexEvent};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::path::PathBuf;
+
+    /// A trial is a Stimulus with a Prelude and Advance frame
+    /// 
+    /// + correct are the keys that count as correct, any of them. 
+    ///   Empty for trials without a correct answer.
+    /// + feedback is an optional feedback frame after the response
+    /// + condition is a tag for the experimental condition
+    /// + mask is a stimulus that follows the target after an SOA, 
+    ///   for backward masking
+    /// + fixation rejects the trial when gaze leaves the center during presentation
+    /// + grader scores key presses with partial credit, instead of correct
+
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
+    pub struct Trial {
+        pub prelude: Prelude,
+        pub stimulus: Stimulus,
+        pub advance: Advance,
+        pub correct: Vec<Key>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::ms::pair"))]
+        pub feedback: Option<(FeedbackMode, Duration)>,
+        pub condition: Option<String>,
+        #[cfg_attr(feature = "serde", serde(with = "crate::ms::pair"))]
+        pub mask: Option<(Stimulus, Duration)>,
+        pub fixation: Option<FixationCheck>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        pub grader: Option<Grader>,
+        #[cfg_attr(feature = "serde", serde(skip))]
+        pub state: State
+    }
+
+    /// Partial credit scoring
+    /// 
+    /// maps a key press and RT to a credit between 0 and 1, 
+    /// e.g. for estimation or confidence-weighted tasks. 
+    /// Graders are equal when they are the same function.
+    #[derive(Clone)]
+    pub struct Grader(pub Arc<Grade>);
+
+    pub type Grade = dyn Fn(Key, Duration) -> f32 + Send + Sync;
+
+    impl std::fmt::Debug for Grader {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.debug_struct("Grader").finish_non_exhaustive()
+        }
+    }
+
+    impl PartialEq for Grader {
+        fn eq(&self, other: &Self) -> bool {
+            Arc::ptr_eq(&self.0, &other.0)
+        }
+    }
+
+    /// Fixation stability
+    /// 
+    /// Gaze is in normalized screen coordinates, the center is (0.5, 0.5). 
+    /// A break is gaze outside the radius around the center, 
+    /// which rejects the trial when it lasts longer than max_break.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct FixationCheck {
+        pub radius: f32,
+        #[cfg_attr(feature = "serde", serde(with = "crate::ms"))]
+        pub max_break: Duration,
+    }
+
+    impl FixationCheck {
+        /// Fixation breaks longer than max_break
+        /// 
+        /// as start from onset and duration. A break still going on at the end 
+        /// of presentation lasts until the end.
+        pub fn breaks(&self, samples: &[GazeSample], onset: Instant, end: Instant) -> Vec<(Duration, Duration)> {
+            let mut breaks = Vec::new();
+            let mut started: Option<Instant> = None;
+            for sample in samples.iter().filter(|sample| sample.time >= onset && sample.time <= end) {
+                let outside = (sample.x - 0.5).hypot(sample.y - 0.5) > self.radius;
+                match (outside, started) {
+                    (true, None) => started = Some(sample.time),
+                    (false, Some(start)) => {
+                        breaks.push((start, sample.time));
+                        started = None;
+                    },
+                    _ => {},
+                }
+            }
+            if let Some(start) = started {
+                breaks.push((start, end));
+            }
+            breaks.into_iter()
+                .filter(|(start, stop)| *stop - *start > self.max_break)
+                .map(|(start, stop)| (start - onset, stop - start))
+                .collect()
+        }
+    }
+    
+    /// Trial states
+    /// 
+    /// Present has the stimulus and the condition of the trial, 
+    /// e.g. for the trial_type of BIDS events.
+    #[derive(Clone, PartialEq, Debug)]
+    pub enum State {
+        Init,
+        Prelude,
+        Present(Stimulus, Option<Text>),
+        Feedback(Feedback)
+    }
+    
+    impl Default for Trial {
+        fn default() -> Self {
+            Self {  state: State::Init,
+                    prelude: Prelude::Blank(Duration::from_micros(500)) ,
+                    stimulus: Stimulus::Blank(Duration::from_micros(500)),
+                    advance: Advance::Wait(Duration::from_millis(500)),
+                    correct: Vec::new(),
+                    feedback: None,
+                    condition: None,
+                    mask: None,
+                    fixation: None,
+                    grader: None}
+        }
+    }
+
+    /// Building a trial
+    /// 
+    /// Unset fields keep their defaults, the state starts at Init.
+    #[derive(Clone, Debug, Default)]
+    pub struct TrialBuilder {
+        trial: Trial,
+    }
+
+    impl Trial {
+        pub fn builder() -> TrialBuilder {
+            TrialBuilder::default()
+        }
+    }
+
+    impl TrialBuilder {
+        pub fn prelude(mut self, prelude: Prelude) -> Self {
+            self.trial.prelude = prelude;
+            self
+        }
+
+        pub fn stimulus(mut self, stimulus: Stimulus) -> Self {
+            self.trial.stimulus = stimulus;
+            self
+        }
+
+        pub fn advance(mut self, advance: Advance) -> Self {
+            self.trial.advance = advance;
+            self
+        }
+
+        /// A key that counts as correct, can be given more than once
+        pub fn correct(mut self, key: Key) -> Self {
+            self.trial.correct.push(key);
+            self
+        }
+
+        pub fn feedback(mut self, mode: FeedbackMode, dur: Duration) -> Self {
+            self.trial.feedback = Some((mode, dur));
+            self
+        }
+
+        pub fn condition(mut self, condition: &str) -> Self {
+            self.trial.condition = Some(condition.into());
+            self
+        }
+
+        pub fn mask(mut self, mask: Stimulus, soa: Duration) -> Self {
+            self.trial.mask = Some((mask, soa));
+            self
+        }
+
+        pub fn fixation(mut self, fixation: FixationCheck) -> Self {
+            self.trial.fixation = Some(fixation);
+            self
+        }
+
+        pub fn grader(mut self, grader: Grader) -> Self {
+            self.trial.grader = Some(grader);
+            self
+        }
+
+        pub fn build(self) -> Trial {
+            self.trial
+        }
+    }
+    
+    impl Trial {
+        /// Estimated duration, see Block::estimated_duration
+        pub fn estimated_duration(&self) -> Duration {
+            let prelude = match self.prelude {
+                Prelude::Blank(dur) | Prelude::Fix(dur) | Prelude::Prime(dur, _) => dur,
+                Prelude::Now => Duration::ZERO,
+            };
+            let advance = match &self.advance {
+                Advance::Wait(dur) | Advance::KeysMaxWait(_, dur) | Advance::Chord(_, _, dur) => *dur,
+                Advance::KeysMaxWaitGrace(_, dur, grace) => *dur + *grace,
+                Advance::Keys(_) | Advance::LabeledKeys(_) => Duration::ZERO,
+                Advance::FreeText(max_wait, _) => max_wait.unwrap_or_default(),
+                Advance::Required(_, reprompt_after, max_reprompts) 
+                    => *reprompt_after * (*max_reprompts as u32 + 1),
+            };
+            let feedback = self.feedback.map_or(Duration::ZERO, |(_, dur)| dur);
+            prelude + advance + feedback
+        }
+        
+        /// Loading the stimuli, so that missing files 
+        /// stop the trial before the presentation. 
+        /// Stimuli that are loaded already are kept, see Block::prepare. 
+        /// A chord without keys fails with EmptyChord, as it would complete on any key.
+        pub fn prepare(&mut self) -> Result<Self, YexError> {
+            if matches!(&self.advance, Advance::Chord(keys, _, _) if keys.is_empty()) {
+                return Err(YexError::EmptyChord)
+            }
+            self.stimulus.load()?;
+            if let Prelude::Prime(_, prime) = &mut self.prelude {
+                prime.load()?;
+            }
+            if let Some((mask, _)) = self.mask.as_mut() {
+                mask.load()?;
+            }
+            Ok(self.clone())
+        }
+
+        /// The trial without the bitmaps of its stimuli, see Stimulus::unloaded
+        pub fn unloaded(&self) -> Self {
+            let mut trial = self.clone();
+            trial.stimulus = self.stimulus.unloaded();
+            if let Prelude::Prime(_, prime) = &mut trial.prelude {
+                *prime = prime.unloaded();
+            }
+            if let Some((mask, _)) = trial.mask.as_mut() {
+                *mask = mask.unloaded();
+            }
+            trial
+        }
+
+        /// Configured durations, for validation
+        pub fn durations(&self) -> Vec<(&'static str, Duration)> {
+            let mut durations = Vec::new();
+            if let Prelude::Blank(dur) | Prelude::Fix(dur) | Prelude::Prime(dur, _) = self.prelude {
+                durations.push(("prelude", dur));
+            }
+            durations.push(("stimulus", self.stimulus.duration()));
+            match &self.advance {
+                Advance::Wait(dur) | Advance::KeysMaxWait(_, dur) => durations.push(("advance", *dur)),
+                Advance::KeysMaxWaitGrace(_, dur, grace) => {
+                    durations.push(("advance", *dur));
+                    durations.push(("grace", *grace));
+                },
+                Advance::Required(_, reprompt_after, _) => durations.push(("reprompt", *reprompt_after)),
+                Advance::Chord(_, window, max_wait) => {
+                    durations.push(("chord window", *window));
+                    durations.push(("advance", *max_wait));
+                },
+                Advance::FreeText(Some(max_wait), _) => durations.push(("advance", *max_wait)),
+                Advance::Keys(_) | Advance::LabeledKeys(_) | Advance::FreeText(None, _) => {},
+            }
+            if let Some((_, dur)) = self.feedback {
+                durations.push(("feedback", dur));
+            }
+            if let Some((mask, soa)) = &self.mask {
+                durations.push(("mask SOA", *soa));
+                durations.push(("mask", mask.duration()));
+            }
+            durations
+        }
+
+        /// Whether two trials have the same design
+        /// 
+        /// compares what is presented and expected, 
+        /// but not the feedback, which can be withheld by chance, 
+        /// nor whether the stimuli are loaded.
+        pub fn same_design(&self, other: &Trial) -> bool {
+            let (this, other) = (self.unloaded(), other.unloaded());
+            this.prelude == other.prelude
+                && this.stimulus == other.stimulus
+                && this.advance == other.advance
+                && this.correct == other.correct
+                && this.condition == other.condition
+                && this.mask == other.mask
+                && this.fixation == other.fixation
+        }
+
+        /// Scoring a key press
+        /// 
+        /// by the grader, or else against the correct keys, if the trial has any. 
+        /// Credit is clamped to 0..1, NaN is no credit.
+        pub fn score(&self, key: Key, rt: Duration) -> Response {
+            if let Some(grader) = &self.grader {
+                let credit = (grader.0)(key, rt);
+                Response::GradedCorrect(if credit.is_nan() {0.} else {credit.clamp(0., 1.)})
+            } else if self.correct.is_empty() {
+                Response::RT(rt)
+            } else {
+                Response::RTCorrect(rt, self.correct.contains(&key))
+            }
+        }
+
+        /// Correct keys that the advance does not take
+        pub fn unreachable_correct_keys(&self) -> Vec<Key> {
+            let keys = self.advance.keys();
+            self.correct.iter().copied().filter(|key| !keys.contains(key)).collect()
+        }
+
+        /// Running a trial
+        /// 
+        /// returns the observation. When the participant quits or the input
+        /// is gone, the trial is recorded as interrupted and the run aborts.
+        pub fn run(&mut self, events_out: Sender<YexRecord>, responder: &mut Responder) 
+                -> Result<Observation, Aborted> {
+            let hooks = TrialHooks{score: &mut |trial: &Trial, key, rt| trial.score(key, rt),
+                                   before_stimulus: None, 
+                                   after_stimulus: None};
+            self.run_with(events_out, responder, hooks)
+        }
+
+        /// Running a trial with hooks
+        pub fn run_with(&mut self, events_out: Sender<YexRecord>, responder: &mut Responder, 
+                        hooks: TrialHooks) 
+                -> Result<Observation, Aborted> {
+            let TrialHooks{score, before_stimulus, after_stimulus} = hooks;
+            let start = Instant::now();
+            events_out.send(YexEvent::Trial(self.state.clone()).into()).unwrap();
+            self.prepare().map_err(|error| Aborted{error, observations: Vec::new()})?;
+            self.state = State::Prelude;
+            events_out.send(YexEvent::Trial(self.state.clone()).into()).unwrap();
+            match self.prelude {
+                Prelude::Now => {},
+                Prelude::Blank(dur) | Prelude::Fix(dur) 
+                    => {sleep(dur);},
+                Prelude::Prime(dur, ref prime) => {
+                    events_out.send(YexEvent::Stimulus(prime.clone()).into()).unwrap();
+                    sleep(dur);
+                },
+            }
+            self.state = State::Present(self.stimulus.clone(), self.condition.clone());
+            if let Some(before_stimulus) = before_stimulus {
+                before_stimulus(self);
+            }
+            events_out.send(YexEvent::Trial(self.state.clone()).into()).unwrap();
+            let onset = Instant::now();
+            if let Some(triggers) = &responder.triggers {
+                triggers.send(self.condition.as_deref());
+            }
+            // Collecting the response from the participant.
+            // 
+            // Here we have time-outs and user events intermixed. 
+            // The responder blocks on the input channel until the deadline, 
+            // so durations are honored without an executor.
+            responder.start_response_window();
+            let mut reprompts = 0;
+            // the mask comes at the SOA, in the middle of the response window or after it
+            let mut mask = self.mask.clone().map(|(mask, soa)| (mask, onset + soa));
+            let mut mask_onset = None;
+            let mut typed = Text::new();
+            let chord = match &self.advance {
+                Advance::Chord(keys, window, _) => Some((keys.clone(), *window)),
+                _ => None,
+            };
+            let free_text = match &self.advance {
+                Advance::FreeText(_, terminator) => Some(*terminator),
+                _ => None,
+            };
+            let mut wait = |keys: &[Key], deadline: Option<Instant>| 
+                    -> Result<Option<(Key, Instant)>, YexError> {
+                let mut respond = |responder: &mut Responder, deadline: Option<Instant>| match (&chord, free_text) {
+                    (Some((chord, window)), _) => responder.wait_chord(chord, *window, deadline),
+                    (None, Some(terminator)) => Ok(responder.read_text_into(&mut typed, terminator, deadline)?
+                                                   .map(|time| (terminator, time))),
+                    (None, None) => responder.wait_key(keys, deadline),
+                };
+                if let Some((stim, at)) = mask.take() {
+                    if deadline.map_or(false, |deadline| deadline <= at) {
+                        mask = Some((stim, at));
+                    } else {
+                        let pressed = respond(responder, Some(at))?;
+                        if pressed.is_some() {
+                            mask = Some((stim, at));
+                            return Ok(pressed)
+                        }
+                        events_out.send(YexEvent::Stimulus(stim).into()).unwrap();
+                        mask_onset = Some(onset.elapsed());
+                    }
+                }
+                respond(responder, deadline)
+            };
+            let pressed = match &self.advance {
+                Advance::Wait(dur) 
+                    => wait(&[], Some(onset + *dur)),
+                Advance::Keys(keys) 
+                    => wait(keys, None),
+                Advance::KeysMaxWait(keys, dur) 
+                    => wait(keys, Some(onset + *dur)),
+                Advance::KeysMaxWaitGrace(keys, dur, grace) 
+                    => wait(keys, Some(onset + *dur + *grace)),
+                Advance::LabeledKeys(_) 
+                    => wait(&self.advance.keys(), None),
+                Advance::Required(keys, reprompt_after, max_reprompts) => loop {
+                    let pressed = wait(keys, Some(Instant::now() + *reprompt_after));
+                    if !matches!(pressed, Ok(None)) || reprompts == *max_reprompts {break pressed}
+                    // presenting the stimulus again, which is not a new onset
+                    reprompts += 1;
+                    events_out.send(YexEvent::Stimulus(self.stimulus.clone()).into()).unwrap();
+                },
+                Advance::Chord(_, _, max_wait) 
+                    => wait(&[], Some(onset + *max_wait)),
+                Advance::FreeText(max_wait, _) 
+                    => wait(&[], max_wait.map(|max_wait| onset + max_wait)),
+            };
+            // a response before the SOA does not take the mask away
+            if let (Some((stim, at)), Ok(_)) = (mask, &pressed) {
+                sleep(at.saturating_duration_since(Instant::now()));
+                events_out.send(YexEvent::Stimulus(stim).into()).unwrap();
+                mask_onset = Some(onset.elapsed());
+            }
+            let typed = free_text.map(|_| typed);
+            let presented = Instant::now();
+            if let Some(after_stimulus) = after_stimulus {
+                after_stimulus(self);
+            }
+            // a Quit means the trial is recorded as interrupted
+            let (pressed, error) = match pressed {
+                Ok(pressed) => (pressed, None),
+                Err(error) => (None, Some(error)),
+            };
+            let response = match (pressed, &error) {
+                (_, Some(_)) => Response::Interrupted,
+                (Some((_, time)), None) if chord.is_some() => Response::RT(time - onset),
+                (Some(_), None) if typed.is_some() => Response::Text(typed.unwrap_or_default()),
+                (Some((key, time)), None) => score(self, key, time - onset),
+                (None, None) => Response::TooLate,
+            };
+            // keys arriving during the grace period count, but are flagged
+            let late = match (&self.advance, pressed) {
+                (Advance::KeysMaxWaitGrace(_, dur, _), Some((_, time))) 
+                    => time - onset >= *dur,
+                _ => false,
+            };
+            events_out.send(YexEvent::Response(response.clone()).into()).unwrap();
+            let mut shown = None;
+            if let (Some((mode, dur)), None) = (self.feedback, &error) {
+                let feedback = mode.select(self, &response);
+                self.state = State::Feedback(feedback.clone());
+                events_out.send(YexEvent::Trial(self.state.clone()).into()).unwrap();
+                sleep(dur);
+                shown = Some(feedback);
+            }
+            let mut obs = Observation::new(self.unloaded(), response);
+            obs.late = late;
+            obs.feedback = shown;
+            obs.reprompts = reprompts;
+            obs.mask_onset = mask_onset;
+            obs.flip_times = responder.flip_times(start, presented);
+            if let Some(fixation) = self.fixation {
+                let samples = responder.gaze_samples(onset, presented);
+                obs.fixation_breaks = fixation.breaks(&samples, onset, presented);
+                obs.rejected = !obs.fixation_breaks.is_empty();
+            }
+            obs.key_events = responder.timeline.drain(..)
+                .map(|(key, time)| (key, time.saturating_duration_since(onset)))
+                .collect();
+            obs.locked_out = responder.locked_out.drain(..)
+                .map(|(key, time)| (key, time.saturating_duration_since(onset)))
+                .collect();
+            match error {
+                None => Ok(obs),
+                Some(error) => Err(Aborted{error, observations: vec![obs]}),
+            }
+        }
+    }
+
+    /// Hooks into a running trial
+    /// 
+    /// + score is the scoring of a key press
+    /// + before_stimulus fires right before the onset, 
+    ///   after_stimulus right after the stimulus clears, 
+    ///   e.g. for shutters or stimulators
+    pub struct TrialHooks<'a> {
+        pub score: &'a mut dyn FnMut(&Trial, Key, Duration) -> Response,
+        pub before_stimulus: Option<&'a StimulusHook>,
+        pub after_stimulus: Option<&'a StimulusHook>,
+    }
+
+    pub type StimulusHook = dyn Fn(&Trial) + Send + Sync;
+
+    #[derive(Clone, PartialEq, Debug)]
+    pub struct Observation {
+        pub trial: Trial,
+        pub response: Response,
+        pub key_events: Vec<(Key, Duration)>,
+        pub late: bool,
+        pub is_block_first: bool,
+        pub feedback: Option<Feedback>,
+        pub locked_out: Vec<(Key, Duration)>,
+        pub reprompts: usize,
+        pub mask_onset: Option<Duration>,
+        pub rejected: bool,
+        pub fixation_breaks: Vec<(Duration, Duration)>,
+        pub participant: usize,
+        pub experiment: Arc<str>,
+        pub block: usize,
+        pub trial_index: usize,
+        pub flip_times: Vec<Instant>,
+    }
+
+    /// An observation is composed of a trial and an observation
+    /// 
+    /// + key_events are all keys pressed during the response window, 
+    ///   with their time from stimulus onset
+    /// + late marks a response that came in the grace period after the deadline
+    /// + is_block_first marks the first trial of a block, which often has atypical RT
+    /// + feedback is what was shown in the feedback frame
+    /// + locked_out are keys ignored during the response lockout
+    /// + reprompts counts how often a required response was prompted again
+    /// + mask_onset is when the mask was presented, from stimulus onset
+    /// + rejected marks a trial with broken fixation, 
+    ///   fixation_breaks has start and duration of the breaks
+    /// + participant and experiment are the ids of the session, 
+    ///   block is the index of the block in the experiment
+    /// + trial_index is the index of the trial in the block, 
+    ///   whereas the observations come in the order of presentation
+    /// + flip_times are the frame flips the renderer reported 
+    ///   until the stimulus cleared, for timing audits
+    /// 
+    /// The trial is kept without the bitmaps of its stimuli, 
+    /// which would pile up over a long session.
+
+    impl Observation {
+        pub fn interrupted(&self) -> bool {
+            self.response == Response::Interrupted
+        }
+
+        pub fn new(trial: Trial, response: Response) -> Self {
+            Self{trial, response, key_events: Vec::new(), late: false,
+                 is_block_first: false, feedback: None, locked_out: Vec::new(),
+                 reprompts: 0, mask_onset: None,
+                 rejected: false, fixation_breaks: Vec::new(), 
+                 participant: 0, experiment: "".into(), block: 0, trial_index: 0, flip_times: Vec::new()}
+        }
+    }
+
+    use image;
+    /// Stimuli
+    /// 
+    /// + Animation is a sequence of decoded frames played at a rate (fps), 
+    ///   looping over the duration. The renderer picks the current frame 
+    ///   with frame_at(), based on the time since onset. 
+    ///   Unloaded, an animation has no frames.
+    /// + Text is the string with size in pixels, RGB color and the script of its language. 
+    ///   The last field is the rasterized text, filled by load(), 
+    ///   so that presentation only blits a bitmap.
+    /// + Image is read from the path by load(), and drawn into the rectangle. 
+    /// 
+    /// The bitmaps are shared between clones of a loaded stimulus,
+    /// observations keep none, see unloaded().
+    /// Serialized, the bitmaps are left out, 
+    /// animations are built in code.
+    #[derive(Clone, Debug, PartialEq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Stimulus {
+        Blank(#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration),
+        Text(#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration, Text, u16, [u8; 3], Script, 
+             #[cfg_attr(feature = "serde", serde(skip))] Option<Arc<image::RgbaImage>>),
+        Image(#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration, PathBuf, [usize; 4], 
+              #[cfg_attr(feature = "serde", serde(skip))] Option<Arc<image::RgbaImage>>),
+        #[cfg_attr(feature = "serde", serde(skip))]
+        Animation(Duration, Arc<[image::RgbaImage]>, f32),
+    }
+
+    /// The largest text size in pixels, about half a screen
+    pub const MAX_TEXT_SIZE: u16 = 512;
+
+    impl Stimulus{
+        /// Text in the script of the language
+        /// 
+        /// Fails with ZeroTextSize, as such a text cannot be seen, 
+        /// and with TextTooLarge above MAX_TEXT_SIZE.
+        pub fn text(dur: Duration, text: &str, size: u16, color: [u8; 3], lang: Language) 
+                -> Result<Self, YexError> {
+            if size == 0 {return Err(YexError::ZeroTextSize)}
+            if size > MAX_TEXT_SIZE {return Err(YexError::TextTooLarge(size))}
+            Ok(Stimulus::Text(dur, text.into(), size, color, Script::from_language(lang), None))
+        }
+
+        /// Preparing the stimulus for presentation
+        /// 
+        /// rasterizes text with the embedded font and reads images, once. 
+        /// Fails with FileNotFound when an image cannot be read, 
+        /// and with ZeroTextSize or TextTooLarge like text(), 
+        /// for text that was built directly or deserialized.
+        pub fn load(&mut self) -> Result<&Self, YexError> {
+            if let Stimulus::Text(_, text, size, color, script, raster @ None) = self {
+                if *size == 0 {return Err(YexError::ZeroTextSize)}
+                if *size > MAX_TEXT_SIZE {return Err(YexError::TextTooLarge(*size))}
+                *raster = crate::font::rasterize(text, *size as u32, *color, *script).map(Arc::new);
+            }
+            if let Stimulus::Image(_, path, _, bitmap @ None) = self {
+                let image = image::open(&*path)
+                    .map_err(|_| YexError::FileNotFound(path.clone()))?;
+                *bitmap = Some(Arc::new(image.into_rgba8()));
+            }
+            Ok(self)
+        }
+
+        /// The stimulus without the bitmaps of load()
+        pub fn unloaded(&self) -> Self {
+            match self {
+                Stimulus::Text(dur, text, size, color, script, _) 
+                    => Stimulus::Text(*dur, text.clone(), *size, *color, *script, None),
+                Stimulus::Image(dur, path, rect, _) 
+                    => Stimulus::Image(*dur, path.clone(), *rect, None),
+                Stimulus::Animation(dur, _, fps) 
+                    => Stimulus::Animation(*dur, Arc::new([]), *fps),
+                Stimulus::Blank(dur) => Stimulus::Blank(*dur),
+            }
+        }
+
+        pub fn duration(&self) -> Duration {
+            match self {
+                Stimulus::Blank(dur) 
+                | Stimulus::Text(dur, _, _, _, _, _) 
+                | Stimulus::Image(dur, _, _, _) 
+                | Stimulus::Animation(dur, _, _) => *dur,
+            }
+        }
+
+        pub fn kind(&self) -> &'static str {
+            match self {
+                Stimulus::Blank(_) => "blank",
+                Stimulus::Text(..) => "text",
+                Stimulus::Image(..) => "image",
+                Stimulus::Animation(_, _, _) => "animation",
+            }
+        }
+
+        /// Index of the animation frame to show at a time since onset
+        /// 
+        /// None for other stimuli, an empty animation 
+        /// and after the duration has passed
+        pub fn frame_index(&self, since_onset: Duration) -> Option<usize> {
+            match self {
+                Stimulus::Animation(dur, frames, fps) 
+                    if since_onset < *dur && !frames.is_empty() 
+                    => Some((since_onset.as_secs_f32() * fps) as usize % frames.len()),
+                _ => None,
+            }
+        }
+
+        pub fn frame_at(&self, since_onset: Duration) -> Option<&image::RgbaImage> {
+            match self {
+                Stimulus::Animation(_, frames, _) 
+                    => frames.get(self.frame_index(since_onset)?),
+                _ => None,
+            }
+        }
+
+        /// Number of frames presented over the duration
+        pub fn frames_presented(&self) -> usize {
+            match self {
+                Stimulus::Animation(dur, frames, fps) if !frames.is_empty() 
+                    => (dur.as_secs_f32() * fps).ceil() as usize,
+                _ => 0,
+            }
+        }
+    }
+
+    /// Script of a text, telling the renderer how to lay it out
+    /// 
+    /// + Rtl is written right to left, like Arabic and Hebrew
+    /// + Wide has wide glyphs, like Chinese, Japanese and Korean
+    #[derive(Clone, Copy, Debug, PartialEq, Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Script {
+        #[default]
+        Ltr,
+        Rtl,
+        Wide,
+    }
+
+    impl Script {
+        pub fn from_language(lang: Language) -> Self {
+            match lang {
+                Language::Ara | Language::Heb | Language::Fas | Language::Pes 
+                | Language::Urd | Language::Yid => Script::Rtl,
+                Language::Zho | Language::Cmn | Language::Jpn | Language::Kor => Script::Wide,
+                _ => Script::Ltr,
+            }
+        }
+
+        pub fn is_rtl(&self) -> bool {
+            *self == Script::Rtl
+        }
+    }
+
+    /// Trial preludes
+    /// 
+    /// + Prime presents a stimulus for the duration, before the target. 
+    ///   A blank prime is a timed blank.
+    #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Prelude {
+        Now,
+        Blank(#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration),
+        Fix(#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration),
+        Prime(#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration, Stimulus),
+    }
+
+    #[derive(Clone, PartialEq, Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum Advance {
+        Wait(#[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration),
+        Keys(Vec<Key>),
+        KeysMaxWait(Vec<Key>, #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration),
+        /// keys, deadline and a grace period after the deadline
+        KeysMaxWaitGrace(Vec<Key>, #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration, #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration),
+        /// keys with what they stand for, e.g. ('f', "left")
+        LabeledKeys(Vec<(Key, Text)>),
+        /// keys, time until the prompt is repeated and the maximum of repeats. 
+        /// Like an item in a questionnaire, that must be answered. 
+        /// After the last repeat, the response is missing (TooLate). 
+        /// The RT is measured from the first prompt.
+        Required(Vec<Key>, #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration, usize),
+        /// keys that must be down together, the window in which they are pressed 
+        /// and the maximum wait. The RT is measured to the completion of the chord. 
+        /// Completing the chord is the only answer, so it is recorded as a plain RT, 
+        /// without the correct keys. A missed chord is TooLate.
+        Chord(HashSet<Key>, #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration, #[cfg_attr(feature = "serde", serde(with = "crate::ms"))] Duration),
+        /// typing text until the terminator key, e.g. '\n', 
+        /// with an optional maximum wait. Backspace ('\u{8}') deletes.
+        FreeText(#[cfg_attr(feature = "serde", serde(with = "crate::ms::option"))] Option<Duration>, Key),
+    }
+
+    impl Advance {
+        /// The keys that advance the trial
+        pub fn keys(&self) -> Vec<Key> {
+            match self {
+                Advance::Wait(_) => Vec::new(),
+                Advance::Keys(keys) 
+                | Advance::KeysMaxWait(keys, _) 
+                | Advance::KeysMaxWaitGrace(keys, _, _) 
+                | Advance::Required(keys, _, _) => keys.clone(),
+                Advance::LabeledKeys(labels) => labels.iter().map(|(key, _)| *key).collect(),
+                Advance::FreeText(_, terminator) => vec![*terminator],
+                Advance::Chord(keys, _, _) => {
+                    let mut keys: Vec<Key> = keys.iter().copied().collect();
+                    keys.sort();
+                    keys
+                },
+            }
+        }
+
+        /// The label of a key, with labeled keys
+        pub fn label(&self, key: Key) -> Option<Text> {
+            let Advance::LabeledKeys(labels) = self else {return None};
+            labels.iter().find(|(labeled, _)| *labeled == key).map(|(_, label)| label.clone())
+        }
+
+        /// Instruction for labeled keys, 
+        /// 
+        /// like "Press F for left, J for right.", in English, German or Dutch.
+        /// Other languages get English. Empty for all other types of Advance.
+        pub fn instruction_text(&self, lang: Language) -> Text {
+            let Advance::LabeledKeys(labels) = self else {return Text::new()};
+            let (press, for_) = match lang {
+                Language::Deu => ("Drücke", "für"),
+                Language::Nld => ("Druk op", "voor"),
+                _ => ("Press", "for"),
+            };
+            let mapping: Vec<String> = labels.iter()
+                .map(|(key, label)| format!("{} {} {}", key.to_uppercase(), for_, label))
+                .collect();
+            format!("{} {}.", press, mapping.join(", "))
+        }
+    }
+
+    /// + Text is typed text, without the terminator
+    /// + Graded is a grade on any scale, e.g. a rating, which is not scored
+    /// + GradedCorrect is partial credit from 0 to 1, see Grader, 
+    ///   which counts as accuracy
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Response {
+        RT(Duration),
+        RTCorrect(Duration, bool),
+        Choice(Key),
+        Graded(f32),
+        GradedCorrect(f32),
+        Text(Text),
+        TooLate,
+        Interrupted,
+    }
+
+    impl Response {
+        pub fn kind(&self) -> &'static str {
+            match self {
+                Response::RT(_) => "RT",
+                Response::RTCorrect(_, _) => "RTCorrect",
+                Response::Choice(_) => "Choice",
+                Response::Graded(_) => "Graded",
+                Response::GradedCorrect(_) => "GradedCorrect",
+                Response::Text(_) => "Text",
+                Response::TooLate => "TooLate",
+                Response::Interrupted => "Interrupted",
+            }
+        }
+
+        pub fn rt(&self) -> Option<Duration> {
+            match self {
+                Response::RT(rt) | Response::RTCorrect(rt, _) => Some(*rt),
+                _ => None,
+            }
+        }
+
+        pub fn correct(&self) -> Option<bool> {
+            match self {
+                Response::RTCorrect(_, correct) => Some(*correct),
+                _ => None,
+            }
+        }
+
+        /// Partial credit of a graded response
+        pub fn credit(&self) -> Option<f32> {
+            match self {
+                Response::GradedCorrect(credit) => Some(*credit),
+                _ => None,
+            }
+        }
+
+        /// Accuracy of a scored response, 0 or 1 if correct, or the credit
+        pub fn accuracy(&self) -> Option<f32> {
+            self.correct().map(|correct| correct as u8 as f32).or(self.credit())
+        }
+
+        pub fn text(&self) -> Option<&str> {
+            match self {
+                Response::Text(text) => Some(text),
+                _ => None,
+            }
+        }
+    }
+
+    /// Feedback shown to the participant
+    /// 
+    /// CorrectAnswer carries the key that would have been correct, 
+    /// the first when there are several, and its label with labeled keys.
+    #[derive(Clone, PartialEq, Debug)]
+    pub enum Feedback{Correct, Incorrect, ThankYou, CorrectAnswer(Key, Option<Text>)}
+
+    /// Feedback modes
+    /// 
+    /// + Outcome tells whether the response was correct
+    /// + CorrectAnswer shows the correct answer, as in learning tasks
+    /// + ThankYou for trials without a correct/incorrect concept
+    /// 
+    /// Trials without a correct key always get ThankYou.
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub enum FeedbackMode{Outcome, CorrectAnswer, ThankYou}
+
+    impl FeedbackMode {
+        /// Feedback for a response
+        /// 
+        /// The outcome of partial credit is correct from half the credit on.
+        pub fn select(&self, trial: &Trial, response: &Response) -> Feedback {
+            let graded = response.credit().is_some();
+            match (self, trial.correct.first().copied()) {
+                (FeedbackMode::Outcome, _) if graded
+                    => if response.accuracy() >= Some(0.5) {Feedback::Correct} else {Feedback::Incorrect},
+                (FeedbackMode::ThankYou, _) | (_, None) 
+                    => Feedback::ThankYou,
+                (FeedbackMode::CorrectAnswer, Some(key)) 
+                    => Feedback::CorrectAnswer(key, trial.advance.label(key)),
+                (FeedbackMode::Outcome, Some(_)) 
+                    => match response.correct() {
+                        Some(true) => Feedback::Correct,
+                        _ => Feedback::Incorrect,
+                    },
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{channel, Input};
+        use crate::testing::{closed, typing};
+
+        /// A trial taking f and j, with the given correct keys
+        fn choice(correct: &[Key]) -> Trial {
+            Trial{prelude: Prelude::Now, 
+                  advance: Advance::KeysMaxWait(vec!['f', 'j'], Duration::from_millis(500)), 
+                  correct: correct.to_vec(),
+                  ..Trial::default()}
+        }
+
+        #[test]
+        fn text_keeps_bright_colors_and_bounded_size() {
+            let mut text = Stimulus::text(Duration::ZERO, "I", 14, [200, 50, 50], Language::Eng).unwrap();
+            assert!(matches!(text, Stimulus::Text(_, _, 14, [200, 50, 50], _, _)));
+            #[cfg(feature = "embedded_font")]
+            {
+                let Stimulus::Text(.., Some(raster)) = text.load().unwrap() else {panic!("not rasterized")};
+                assert!(raster.pixels().any(|pixel| pixel.0 == [200, 50, 50, 255]));
+            }
+            #[cfg(feature = "serde")]
+            {
+                let trial = Trial{stimulus: text.unloaded(), ..Trial::default()};
+                let file = toml::to_string(&trial).unwrap();
+                assert_eq!(toml::from_str::<Trial>(&file).unwrap(), trial);
+            }
+            text = Stimulus::text(Duration::ZERO, "I", MAX_TEXT_SIZE, [0; 3], Language::Eng).unwrap();
+            assert!(matches!(text, Stimulus::Text(_, _, MAX_TEXT_SIZE, ..)));
+            assert_eq!(Stimulus::text(Duration::ZERO, "I", 60000, [0; 3], Language::Eng), 
+                       Err(YexError::TextTooLarge(60000)));
+            let mut huge = Stimulus::Text(Duration::ZERO, "I".into(), 60000, [0; 3], Script::Ltr, None);
+            assert_eq!(huge.load().unwrap_err(), YexError::TextTooLarge(60000));
+            let mut zero = Stimulus::Text(Duration::ZERO, "I".into(), 0, [0; 3], Script::Ltr, None);
+            assert_eq!(zero.load().unwrap_err(), YexError::ZeroTextSize);
+        }
+
+        #[test]
+        fn arabic_text_is_right_to_left() {
+            let text = Stimulus::text(Duration::ZERO, "مرحبا", 14, [0, 0, 0], Language::Ara).unwrap();
+            assert!(matches!(text, Stimulus::Text(_, _, _, _, Script::Rtl, _)));
+            assert!(Script::from_language(Language::Ara).is_rtl());
+            assert_eq!(Script::from_language(Language::Jpn), Script::Wide);
+            assert!(!Script::from_language(Language::Eng).is_rtl());
+        }
+
+        #[test]
+        #[cfg(feature = "serde")]
+        fn sub_millisecond_durations_round_trip() {
+            let trial = Trial{mask: Some((Stimulus::Blank(Duration::from_millis(30)), Duration::from_micros(16_700))), 
+                              ..Trial::default()};
+            let file = toml::to_string(&trial).unwrap();
+            assert!(file.contains("Blank = 0.5"), "{file}");
+            assert_eq!(toml::from_str::<Trial>(&file).unwrap(), trial);
+            assert!(toml::from_str::<Trial>("advance = { Wait = -1 }").is_err());
+        }
+
+        #[test]
+        fn labeled_keys_are_instructed() {
+            let advance = Advance::LabeledKeys(vec![('f', "left".into()), ('j', "right".into())]);
+            assert_eq!(advance.instruction_text(Language::Eng), "Press F for left, J for right.");
+            assert_eq!(advance.instruction_text(Language::Deu), "Drücke F für left, J für right.");
+            assert_eq!(advance.instruction_text(Language::Nld), "Druk op F voor left, J voor right.");
+            assert_eq!(advance.instruction_text(Language::Fra), "Press F for left, J for right.");
+            assert_eq!(Advance::Keys(vec!['f', 'j']).instruction_text(Language::Eng), "");
+        }
+
+        #[test]
+        fn hooks_surround_the_stimulus() {
+            use std::sync::Mutex;
+            let calls = Arc::new(Mutex::new(Vec::new()));
+            let hook = |name: &'static str| {
+                let calls = calls.clone();
+                move |_: &Trial| calls.lock().unwrap().push((name, Instant::now()))
+            };
+            let (before, after) = (hook("before"), hook("after"));
+            let hooks = TrialHooks{score: &mut |trial: &Trial, key, rt| trial.score(key, rt),
+                                   before_stimulus: Some(&before), 
+                                   after_stimulus: Some(&after)};
+            let (events_out, events) = channel();
+            let mut responder = typing(vec![Input::Key('f')], Duration::from_millis(20));
+            let obs = choice(&['f']).run_with(events_out, &mut responder, hooks).unwrap();
+            let calls = calls.lock().unwrap().clone();
+            assert_eq!(calls.iter().map(|(name, _)| *name).collect::<Vec<_>>(), ["before", "after"]);
+            let at = |present: bool| events.try_iter()
+                .find(|YexRecord(_, event)| match event {
+                    YexEvent::Trial(State::Present(..)) => present,
+                    YexEvent::Response(_) => !present,
+                    _ => false})
+                .map(|YexRecord(time, _)| time)
+                .unwrap();
+            let (onset, response) = (at(true), at(false));
+            // before the onset, after the key and before the response is out
+            assert!(calls[0].1 <= onset);
+            assert!(calls[1].1 >= calls[0].1 + obs.response.rt().unwrap() && calls[1].1 <= response);
+        }
+
+        #[test]
+        fn reprompt_is_not_an_onset() {
+            let (events_out, events) = channel();
+            let mut trial = Trial{advance: Advance::Required(vec!['a'], Duration::from_millis(10), 2), 
+                                  ..Trial::default()};
+            // the input stays open, as a required response needs keys
+            let (_keys, input) = channel();
+            trial.run(events_out, &mut Responder::new(input)).unwrap();
+            let events: Vec<YexEvent> = events.try_iter().map(|crate::YexRecord(_, event)| event).collect();
+            let onsets = events.iter().filter(|event| matches!(event, YexEvent::Trial(State::Present(..)))).count();
+            let repeats = events.iter().filter(|event| matches!(event, YexEvent::Stimulus(_))).count();
+            assert_eq!((onsets, repeats), (1, 2));
+        }
+
+        #[test]
+        fn answer_after_two_reprompts() {
+            let (events_out, _events) = channel();
+            let mut trial = Trial{prelude: Prelude::Now, 
+                                  advance: Advance::Required(vec!['a'], Duration::from_millis(100), 3), 
+                                  ..Trial::default()};
+            // the key comes in the third window
+            let mut responder = typing(vec![Input::Key('a')], Duration::from_millis(250));
+            let obs = trial.run(events_out, &mut responder).unwrap();
+            assert_eq!(obs.reprompts, 2);
+            // the RT counts from the first onset
+            assert!(matches!(obs.response, Response::RT(rt) if rt >= Duration::from_millis(200)));
+        }
+
+        #[test]
+        fn closed_input_interrupts_keys() {
+            let (events_out, _events) = channel();
+            let mut trial = Trial{advance: Advance::Keys(vec!['a']), ..Trial::default()};
+            let aborted = trial.run(events_out, &mut closed()).unwrap_err();
+            assert_eq!(aborted.error, YexError::InputClosed);
+            assert_eq!(aborted.observations.len(), 1);
+            assert!(aborted.observations[0].interrupted());
+        }
+
+        #[test]
+        fn prime_comes_before_the_target() {
+            let (events_out, events) = channel();
+            let prime = Stimulus::Blank(Duration::from_millis(50));
+            let mut trial = Trial{prelude: Prelude::Prime(Duration::from_millis(50), prime.clone()), 
+                                  advance: Advance::Wait(Duration::from_millis(1)), 
+                                  ..Trial::default()};
+            let start = Instant::now();
+            let obs = trial.run(events_out, &mut closed()).unwrap();
+            assert!(start.elapsed() >= Duration::from_millis(50));
+            assert_eq!(obs.trial.prelude, trial.prelude);
+            let presented: Vec<Stimulus> = events.try_iter()
+                .filter_map(|YexRecord(_, event)| match event {
+                    YexEvent::Stimulus(stimulus) | YexEvent::Trial(State::Present(stimulus, _)) => Some(stimulus),
+                    _ => None})
+                .collect();
+            assert_eq!(presented, vec![prime, trial.stimulus.clone()]);
+        }
+
+        #[test]
+        fn missing_prime_fails_in_prepare() {
+            let path = PathBuf::from("no/such/prime.png");
+            let prime = Stimulus::Image(Duration::ZERO, path.clone(), [0, 0, 10, 10], None);
+            let mut trial = Trial{prelude: Prelude::Prime(Duration::from_millis(50), prime), ..Trial::default()};
+            assert_eq!(trial.prepare().unwrap_err(), YexError::FileNotFound(path.clone()));
+            let mut block = crate::block::Block{trials: vec![trial], ..crate::block::Block::default()};
+            assert_eq!(block.prepare(), Err(YexError::FileNotFound(path)));
+        }
+
+        #[test]
+        fn missing_image_is_not_found() {
+            let path = PathBuf::from("no/such/image.png");
+            let mut stim = Stimulus::Image(Duration::ZERO, path.clone(), [0, 0, 10, 10], None);
+            assert_eq!(stim.load().unwrap_err(), YexError::FileNotFound(path));
+        }
+
+        #[test]
+        fn image_is_loaded_but_not_observed() {
+            let path = std::env::temp_dir().join(format!("yex_fixture_{}.png", std::process::id()));
+            image::RgbaImage::new(4, 3).save(&path).unwrap();
+            let mut stim = Stimulus::Image(Duration::ZERO, path.clone(), [0, 0, 4, 3], None);
+            stim.load().unwrap();
+            let Stimulus::Image(_, _, _, Some(bitmap)) = &stim else {panic!("not loaded")};
+            assert_eq!(bitmap.dimensions(), (4, 3));
+            let (events_out, _events) = channel();
+            let mut trial = Trial{stimulus: stim, ..Trial::default()};
+            let obs = trial.run(events_out, &mut closed()).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(obs.trial.stimulus, Stimulus::Image(Duration::ZERO, path, [0, 0, 4, 3], None));
+        }
+
+        #[test]
+        #[cfg(feature = "embedded_font")]
+        fn text_is_rasterized_on_load() {
+            let mut stim = Stimulus::text(Duration::ZERO, "RED", 28, [255, 0, 0], Language::Eng).unwrap();
+            stim.load().unwrap();
+            let Stimulus::Text(_, _, _, _, _, Some(raster)) = &stim else {panic!("not rasterized")};
+            assert_eq!(raster.height(), 28);
+            assert!(raster.width() > raster.height() && raster.width() < 3 * 28);
+            assert!(raster.pixels().any(|pixel| pixel.0 == [255, 0, 0, 255]));
+        }
+
+        #[test]
+        fn key_is_timed_from_onset() {
+            let (events_out, _events) = channel();
+            let (keys, input) = channel();
+            let mut trial = Trial{prelude: Prelude::Now,
+                                  advance: Advance::KeysMaxWait(vec!['j'], Duration::from_millis(500)), 
+                                  ..Trial::default()};
+            std::thread::spawn(move || {
+                sleep(Duration::from_millis(50));
+                keys.send(Input::Key('j')).unwrap();
+            });
+            let obs = trial.run(events_out, &mut Responder::new(input)).unwrap();
+            let rt = obs.response.rt().unwrap();
+            assert!(rt >= Duration::from_millis(45) && rt < Duration::from_millis(250), "{:?}", rt);
+        }
+
+        #[test]
+        fn key_before_onset_is_dropped() {
+            let (events_out, _events) = channel();
+            let (keys, input) = channel();
+            let mut trial = Trial{prelude: Prelude::Fix(Duration::from_millis(100)),
+                                  advance: Advance::KeysMaxWait(vec!['j'], Duration::from_millis(100)), 
+                                  ..Trial::default()};
+            keys.send(Input::Key('j')).unwrap();
+            let obs = trial.run(events_out, &mut Responder::new(input)).unwrap();
+            assert_eq!(obs.response, Response::TooLate);
+            assert!(obs.key_events.is_empty());
+        }
+
+        #[test]
+        fn finished_stream_keeps_timed_trials() {
+            use futures::stream;
+            let (events_out, _events) = channel();
+            let mut responder = Responder::from_stream(stream::iter(vec!['a']));
+            let mut trial = Trial::default();
+            assert!(trial.run(events_out, &mut responder).is_ok());
+        }
+
+        #[test]
+        fn loading_keeps_the_design() {
+            let stim = Stimulus::text(Duration::ZERO, "BLUE", 14, [0, 0, 255], Language::Eng).unwrap();
+            let trial = Trial{stimulus: stim, ..Trial::default()};
+            let mut loaded = trial.clone();
+            loaded.prepare().unwrap();
+            assert!(loaded.same_design(&trial));
+        }
+
+        #[test]
+        fn closed_input_waits_out_timed_trials() {
+            let (events_out, _events) = channel();
+            let mut trial = Trial{advance: Advance::Wait(Duration::from_millis(100)), ..Trial::default()};
+            let start = Instant::now();
+            let obs = trial.run(events_out, &mut closed()).unwrap();
+            assert!(start.elapsed() >= Duration::from_millis(100));
+            assert_eq!(obs.response, Response::TooLate);
+        }
+
+        #[test]
+        fn chord_is_recorded_as_its_rt() {
+            let chord = |inputs: Vec<Input>| {
+                let (events_out, _events) = channel();
+                let mut trial = Trial{prelude: Prelude::Now, 
+                                      advance: Advance::Chord(['a', 's'].into(), Duration::from_millis(100), 
+                                                              Duration::from_millis(300)), 
+                                      ..Trial::default()};
+                // the input stays open until the maximum wait has passed
+                let (keys, input) = channel();
+                std::thread::spawn(move || {
+                    for input in inputs {
+                        sleep(Duration::from_millis(20));
+                        keys.send(input).unwrap();
+                    }
+                    sleep(Duration::from_millis(400));
+                });
+                trial.run(events_out, &mut Responder::new(input)).unwrap()
+            };
+            let obs = chord(crate::testing::keys("xas"));
+            // completed by the second key of the chord
+            assert!(matches!(obs.response, Response::RT(rt) if rt >= Duration::from_millis(60)), "{:?}", obs.response);
+            assert_eq!(obs.key_events.iter().map(|(key, _)| *key).collect::<String>(), "xas");
+            let obs = chord(vec![Input::Key('a'), Input::Release('a'), Input::Key('s')]);
+            assert_eq!(obs.response, Response::TooLate);
+            let mut empty = Trial{advance: Advance::Chord(HashSet::new(), Duration::from_millis(100), Duration::from_millis(300)), 
+                                  ..Trial::default()};
+            assert_eq!(empty.prepare().unwrap_err(), YexError::EmptyChord);
+        }
+
+        #[test]
+        fn typed_text_is_the_response() {
+            let typed = |max_wait: Option<Duration>, responder: &mut Responder| {
+                let (events_out, _events) = channel();
+                let mut trial = Trial{prelude: Prelude::Now, advance: Advance::FreeText(max_wait, '\n'), 
+                                      ..Trial::default()};
+                trial.run(events_out, responder).unwrap()
+            };
+            // backspace takes back a key, the terminator is not part of the text
+            let obs = typed(None, &mut typing(crate::testing::keys("rde\u{8}\u{8}ed\n"), Duration::from_millis(10)));
+            assert_eq!(obs.response, Response::Text("red".into()));
+            assert_eq!(obs.key_events.len(), 8);
+            // without a terminator before the deadline, the response is too late
+            let (_keys, input) = channel();
+            let obs = typed(Some(Duration::from_millis(50)), &mut Responder::new(input));
+            assert_eq!(obs.response, Response::TooLate);
+        }
+
+        #[test]
+        fn mask_follows_at_the_soa() {
+            let masked = |advance: Advance, inputs: Vec<Input>| {
+                let (events_out, events) = channel();
+                let mut trial = Trial{prelude: Prelude::Now, advance, 
+                                      mask: Some((Stimulus::Blank(Duration::from_millis(1)), Duration::from_millis(100))),
+                                      ..Trial::default()};
+                let obs = trial.run(events_out, &mut typing(inputs, Duration::from_millis(20))).unwrap();
+                let masks = events.try_iter()
+                    .filter(|crate::YexRecord(_, event)| matches!(event, YexEvent::Stimulus(Stimulus::Blank(_))))
+                    .count();
+                assert_eq!(masks, 1);
+                assert!(obs.mask_onset.unwrap() >= Duration::from_millis(100));
+                obs
+            };
+            // an early response keeps its RT
+            let early = masked(Advance::KeysMaxWait(vec!['f', 'j'], Duration::from_millis(500)), vec![Input::Key('f')]);
+            assert!(early.response.rt().unwrap() < Duration::from_millis(100));
+            let late = masked(Advance::KeysMaxWait(vec!['f', 'j'], Duration::from_millis(500)), 
+                              vec![Input::Key('x'); 7].into_iter().chain([Input::Key('f')]).collect());
+            assert!(late.response.rt().unwrap() > Duration::from_millis(100));
+            let typed = masked(Advance::FreeText(None, '\n'), crate::testing::keys("red\n"));
+            assert_eq!(typed.response, Response::Text("red".into()));
+            let across = masked(Advance::FreeText(None, '\n'), crate::testing::keys("yellow\n"));
+            assert_eq!(across.response, Response::Text("yellow".into()));
+        }
+
+        fn response(trial: &mut Trial, key: Key) -> Response {
+            let (events_out, _events) = channel();
+            let mut responder = typing(vec![Input::Key(key)], Duration::from_millis(20));
+            trial.run(events_out, &mut responder).unwrap().response
+        }
+
+        #[test]
+        fn matching_key_is_correct() {
+            assert!(matches!(response(&mut choice(&['f']), 'f'), Response::RTCorrect(_, true)));
+        }
+
+        #[test]
+        fn mismatching_key_is_incorrect() {
+            assert!(matches!(response(&mut choice(&['f']), 'j'), Response::RTCorrect(_, false)));
+        }
+
+        #[test]
+        fn either_correct_key_is_correct() {
+            let mut trial = Trial{advance: Advance::KeysMaxWait(vec!['f', 'j', 'k'], Duration::from_millis(500)), 
+                                  ..choice(&['f', 'j'])};
+            assert!(matches!(response(&mut trial, 'f'), Response::RTCorrect(_, true)));
+            assert!(matches!(response(&mut trial, 'j'), Response::RTCorrect(_, true)));
+            assert!(matches!(response(&mut trial, 'k'), Response::RTCorrect(_, false)));
+        }
+
+        #[test]
+        fn grader_gives_partial_credit() {
+            // confidence from 1 to 5, the credit of 9 is beyond 1 and clamped
+            let confidence = |key: Key, _| (key.to_digit(10).unwrap() as f32 - 1.) / 4.;
+            let mut trial = Trial{advance: Advance::KeysMaxWait(vec!['1', '3', '5', '9'], Duration::from_millis(500)), 
+                                  grader: Some(Grader(Arc::new(confidence))),
+                                  ..choice(&['5'])};
+            assert_eq!(response(&mut trial, '1'), Response::GradedCorrect(0.));
+            assert_eq!(response(&mut trial, '3'), Response::GradedCorrect(0.5));
+            assert_eq!(response(&mut trial, '9'), Response::GradedCorrect(1.));
+            assert_eq!(Response::GradedCorrect(0.5).credit(), Some(0.5));
+            // a broken grader gives no credit
+            trial.grader = Some(Grader(Arc::new(|_, _| f32::NAN)));
+            assert_eq!(response(&mut trial, '5'), Response::GradedCorrect(0.));
+        }
+
+        #[test]
+        fn credit_counts_as_accuracy() {
+            let trial = Trial{grader: Some(Grader(Arc::new(|_, _| 0.5))), ..choice(&[])};
+            assert_eq!(FeedbackMode::Outcome.select(&trial, &Response::GradedCorrect(0.75)), Feedback::Correct);
+            assert_eq!(FeedbackMode::Outcome.select(&trial, &Response::GradedCorrect(0.25)), Feedback::Incorrect);
+            assert_eq!(FeedbackMode::ThankYou.select(&trial, &Response::GradedCorrect(0.75)), Feedback::ThankYou);
+            let obs: Vec<_> = [Response::GradedCorrect(0.25), Response::RTCorrect(Duration::from_millis(300), true)]
+                .map(|response| Observation::new(trial.clone(), response)).to_vec();
+            let summary = crate::output::summarize(&obs);
+            assert_eq!(summary.accuracy, Some(0.625));
+            assert_eq!(summary.credit, Some(0.25));
+        }
+
+        #[test]
+        fn no_correct_key_is_plain_rt() {
+            assert!(matches!(response(&mut choice(&[]), 'j'), Response::RT(_)));
+        }
+
+        /// The feedback shown after pressing key, which the trial's state has reached
+        fn feedback(mode: FeedbackMode, key: Key) -> Option<Feedback> {
+            let mut trial = Trial{feedback: Some((mode, Duration::from_millis(10))), ..choice(&['f'])};
+            let (events_out, events) = channel();
+            let mut responder = typing(vec![Input::Key(key)], Duration::from_millis(20));
+            let obs = trial.run(events_out, &mut responder).unwrap();
+            let sent = events.try_iter()
+                .filter_map(|crate::YexRecord(_, event)| match event {
+                    YexEvent::Trial(State::Feedback(feedback)) => Some(feedback),
+                    _ => None,
+                })
+                .last();
+            assert_eq!(trial.state, State::Feedback(obs.feedback.clone().unwrap()));
+            assert_eq!(sent, obs.feedback);
+            obs.feedback
+        }
+
+        #[test]
+        fn outcome_feedback_follows_correctness() {
+            assert_eq!(feedback(FeedbackMode::Outcome, 'f'), Some(Feedback::Correct));
+            assert_eq!(feedback(FeedbackMode::Outcome, 'j'), Some(Feedback::Incorrect));
+            assert_eq!(feedback(FeedbackMode::ThankYou, 'j'), Some(Feedback::ThankYou));
+        }
+
+        #[test]
+        fn wrong_answer_gets_the_correct_one() {
+            assert_eq!(feedback(FeedbackMode::CorrectAnswer, 'j'), Some(Feedback::CorrectAnswer('f', None)));
+            let (events_out, _events) = channel();
+            let mut trial = Trial{prelude: Prelude::Now, 
+                                  advance: Advance::LabeledKeys(vec![('f', "left".into()), ('j', "right".into())]),
+                                  correct: vec!['j'],
+                                  feedback: Some((FeedbackMode::CorrectAnswer, Duration::from_millis(10))),
+                                  ..Trial::default()};
+            let mut responder = typing(vec![Input::Key('f')], Duration::from_millis(20));
+            let obs = trial.run(events_out, &mut responder).unwrap();
+            assert_eq!(obs.response.correct(), Some(false));
+            assert_eq!(obs.feedback, Some(Feedback::CorrectAnswer('j', Some("right".into()))));
+        }
+
+        #[test]
+        fn timeline_keeps_every_key() {
+            let (events_out, _events) = channel();
+            let mut responder = typing(crate::testing::keys("xyj"), Duration::from_millis(20));
+            let obs = choice(&['j']).run(events_out, &mut responder).unwrap();
+            let Response::RTCorrect(rt, true) = obs.response else {panic!("not correct")};
+            let keys: Vec<Key> = obs.key_events.iter().map(|(key, _)| *key).collect();
+            assert_eq!(keys, ['x', 'y', 'j']);
+            assert!(obs.key_events.windows(2).all(|pair| pair[0].1 < pair[1].1));
+            assert_eq!(obs.key_events.last().map(|(_, rt)| *rt), Some(rt));
+        }
+
+        #[test]
+        fn grace_catches_near_misses() {
+            let grace = |after: u64| {
+                let (events_out, _events) = channel();
+                let mut trial = Trial{prelude: Prelude::Now, 
+                                      advance: Advance::KeysMaxWaitGrace(vec!['j'], Duration::from_millis(100), 
+                                                                         Duration::from_millis(100)), 
+                                      correct: vec!['j'],
+                                      ..Trial::default()};
+                let mut responder = typing(vec![Input::Key('j')], Duration::from_millis(after));
+                trial.run(events_out, &mut responder).unwrap()
+            };
+            let within = grace(150);
+            assert!(matches!(within.response, Response::RTCorrect(_, true)));
+            assert!(within.late);
+            let beyond = grace(300);
+            assert_eq!(beyond.response, Response::TooLate);
+            assert!(!beyond.late);
+            let in_time = grace(30);
+            assert!(matches!(in_time.response, Response::RTCorrect(_, true)) && !in_time.late);
+        }
+
+        #[test]
+        fn animation_presents_its_frames_at_the_rate() {
+            let frames: Vec<image::RgbaImage> = (0..4u8)
+                .map(|i| image::RgbaImage::from_pixel(1, 1, image::Rgba([i, 0, 0, 255])))
+                .collect();
+            let animation = Stimulus::Animation(Duration::from_secs(1), frames.into(), 10.);
+            assert_eq!(animation.frames_presented(), 10);
+            // a renderer at 100 Hz, counting frame changes
+            let shown: Vec<usize> = (0..100)
+                .filter_map(|ms10| animation.frame_index(Duration::from_millis(ms10 * 10)))
+                .collect();
+            let changes = 1 + shown.windows(2).filter(|pair| pair[0] != pair[1]).count();
+            assert_eq!(changes, animation.frames_presented());
+            assert_eq!(animation.frame_at(Duration::from_millis(250)).unwrap().get_pixel(0, 0).0[0], 2);
+            assert_eq!(animation.frame_index(Duration::from_millis(450)), Some(0));
+            assert_eq!(animation.frame_index(Duration::from_secs(1)), None);
+            // clones share the frames, observations keep none
+            let Stimulus::Animation(_, frames, _) = &animation else {unreachable!()};
+            let Stimulus::Animation(_, cloned, _) = animation.clone() else {unreachable!()};
+            assert!(Arc::ptr_eq(frames, &cloned));
+            assert_eq!(animation.unloaded().frames_presented(), 0);
+        }
+    }
+}
+
+
+
+/// Input
+/// 
+/// The Responder is the engine's end of the input channel.
+/// Trials ask it for keys, with or without a deadline.
+
+pub mod input {
+    use super::{Instant, Duration, Key, Text, Input, NaviEvent, Receiver, channel, sleep};
+    use super::output::{YexError, Triggers};
+    use futures::Stream;
+    use futures::executor::block_on_stream;
+    use std::thread;
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::collections::{VecDeque, HashSet, HashMap};
+
+    /// A sample from an eye tracker, in normalized screen coordinates
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct GazeSample {
+        pub time: Instant,
+        pub x: f32,
+        pub y: f32,
+    }
+
+    /// Something that delivers gaze samples, like an eye tracker
+    pub trait GazeSource {
+        /// The next sample, or None when the deadline passed first
+        fn next_sample(&mut self, deadline: Instant) -> Option<GazeSample>;
+    }
+
+    impl GazeSource for Receiver<GazeSample> {
+        fn next_sample(&mut self, deadline: Instant) -> Option<GazeSample> {
+            self.recv_timeout(deadline.checked_duration_since(Instant::now())?).ok()
+        }
+    }
+
+    /// A circular region on the screen, in normalized coordinates
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Region {
+        pub x: f32,
+        pub y: f32,
+        pub radius: f32,
+    }
+
+    impl Region {
+        pub fn distance(&self, sample: &GazeSample) -> f32 {
+            (sample.x - self.x).hypot(sample.y - self.y)
+        }
+
+        pub fn contains(&self, sample: &GazeSample) -> bool {
+            self.distance(sample) <= self.radius
+        }
+    }
+
+    /// + timeline collects the keys of the current response window
+    /// + lockout is a period at the start of each trial's response window, 
+    ///   in which keys are not accepted, but collected in locked_out. 
+    ///   Probes and other waits have no lockout.
+    /// + gaze is an optional stream of gaze samples
+    /// + triggers are sent at stimulus onset, e.g. to an EEG amplifier
+    /// + watchdog is a time after which every wait fails, 
+    ///   so that a run without input cannot hang
+    /// + flips are frame flip times from the renderer, see FlipRecorder
+    pub struct Responder {
+        pub input: Receiver<Input>,
+        pub timeline: Vec<(Key, Instant)>,
+        pub allowed_keys: Option<HashSet<Key>>,
+        pub lockout: Duration,
+        pub locked_out: Vec<(Key, Instant)>,
+        lockout_until: Option<Instant>,
+        pending: VecDeque<Input>,
+        pub gaze: Option<Receiver<GazeSample>>,
+        pub triggers: Option<Triggers>,
+        pub watchdog: Option<Instant>,
+        pub flips: Option<Receiver<Instant>>,
+        later_flips: Vec<Instant>,
+        flip_period: Option<(Instant, Instant)>,
+    }
+
+    impl Responder {
+        pub fn new(input: Receiver<Input>) -> Self {
+            Self{input, 
+                 timeline: Vec::new(), 
+                 allowed_keys: None, 
+                 lockout: Duration::ZERO,
+                 locked_out: Vec::new(),
+                 lockout_until: None,
+                 pending: VecDeque::new(),
+                 gaze: None,
+                 triggers: None,
+                 watchdog: None,
+                 flips: None,
+                 later_flips: Vec::new(),
+                 flip_period: None}
+        }
+
+        /// A responder fed by a stream of keys
+        /// 
+        /// e.g. synthetic key presses in tests, or an async input source. 
+        /// A thread forwards the keys to the input channel, 
+        /// when the stream ends, the input is closed.
+        pub fn from_stream<S>(keys: S) -> Self 
+                where S: Stream<Item = Key> + Send + Unpin + 'static {
+            let (keys_out, input) = channel();
+            thread::spawn(move || {
+                for key in block_on_stream(keys) {
+                    if keys_out.send(Input::Key(key)).is_err() {break}
+                }
+            });
+            Self::new(input)
+        }
+
+        /// Gaze samples that arrived for a period
+        /// 
+        /// Older samples are dropped, as well as those after the period.
+        pub fn gaze_samples(&mut self, from: Instant, to: Instant) -> Vec<GazeSample> {
+            let Some(gaze) = self.gaze.as_ref() else {return Vec::new()};
+            gaze.try_iter()
+                .filter(|sample| sample.time >= from && sample.time <= to)
+                .collect()
+        }
+
+        /// Frame flips of a period
+        /// 
+        /// A renderer on its own thread reports flips a little later, but in order. 
+        /// So all flips of the period have arrived with the first flip after it. 
+        /// This does not wait for it, the rest of the period comes with late_flips. 
+        /// Flips after the period are kept for the next one.
+        pub fn flip_times(&mut self, from: Instant, to: Instant) -> Vec<Instant> {
+            let Some(flips) = self.flips.as_ref() else {return Vec::new()};
+            self.later_flips.extend(flips.try_iter());
+            self.flip_period = match self.later_flips.last() {
+                Some(last) if *last > to => None,
+                _ => Some((from, to)),
+            };
+            let (period, later) = self.later_flips.drain(..)
+                .filter(|flip| *flip >= from)
+                .partition(|flip| *flip <= to);
+            self.later_flips = later;
+            period
+        }
+
+        /// Flips of the last period that were reported after flip_times
+        /// 
+        /// e.g. during the ITI, once they are complete, there are no more.
+        pub fn late_flips(&mut self) -> Vec<Instant> {
+            match self.flip_period {
+                Some((from, to)) => self.flip_times(from, to),
+                None => Vec::new(),
+            }
+        }
+
+        /// Starting a response window
+        /// 
+        /// clears the timeline and ends a lockout. 
+        /// Keys are timed when they are read, so keys already waiting 
+        /// were pressed before the window, e.g. during the prelude. 
+        /// They are dropped, instead of being scored at onset. 
+        /// Navigation is kept.
+        pub fn start_window(&mut self) {
+            self.pending.extend(self.input.try_iter());
+            self.pending.retain(|input| matches!(input, Input::Navi(_)));
+            self.timeline.clear();
+            self.locked_out.clear();
+            self.lockout_until = None;
+        }
+
+        /// Starting the response window of a trial, which starts the lockout
+        pub fn start_response_window(&mut self) {
+            self.start_window();
+            self.lockout_until = Some(Instant::now() + self.lockout);
+        }
+
+        /// Keys outside the whitelist never reach the trials
+        pub fn allows(&self, key: Key) -> bool {
+            self.allowed_keys.as_ref().map_or(true, |allowed| allowed.contains(&key))
+        }
+
+        /// Next input, unless the deadline passes first
+        /// 
+        /// A closed input channel, e.g. when the window was closed, 
+        /// is an error when the wait needs a key, so that nobody waits forever. 
+        /// Waits that only run until their deadline sleep until then instead. 
+        /// The watchdog is an error, always.
+        fn next(&mut self, deadline: Option<Instant>, needs_key: bool) -> Result<Option<Input>, YexError> {
+            if let Some(input) = self.pending.pop_front() {
+                return Ok(Some(input))
+            }
+            let needs_key = needs_key || deadline.is_none();
+            let deadline = match (deadline, self.watchdog) {
+                (Some(deadline), Some(watchdog)) => Some(deadline.min(watchdog)),
+                (deadline, watchdog) => deadline.or(watchdog),
+            };
+            match deadline {
+                None => self.input.recv().map(Some).map_err(|_| YexError::InputClosed),
+                Some(deadline) => {
+                    let Some(left) = deadline.checked_duration_since(Instant::now()) 
+                        else {return self.timed_out()};
+                    match self.input.recv_timeout(left) {
+                        Ok(input) => Ok(Some(input)),
+                        Err(RecvTimeoutError::Timeout) => self.timed_out(),
+                        Err(RecvTimeoutError::Disconnected) if needs_key => Err(YexError::InputClosed),
+                        Err(RecvTimeoutError::Disconnected) => {
+                            sleep(deadline.saturating_duration_since(Instant::now()));
+                            self.timed_out()
+                        },
+                    }
+                }
+            }
+        }
+
+        /// A deadline passed, which may be the watchdog
+        fn timed_out(&self) -> Result<Option<Input>, YexError> {
+            match self.watchdog {
+                Some(watchdog) if Instant::now() >= watchdog => Err(YexError::WatchdogTimeout),
+                _ => Ok(None),
+            }
+        }
+
+        /// Waiting for the participant to go on
+        /// 
+        /// with a Forward. Keys and Back are ignored, a Quit interrupts. 
+        /// Returns false when the deadline passed.
+        pub fn wait_forward(&mut self, deadline: Option<Instant>) -> Result<bool, YexError> {
+            loop {
+                match self.next(deadline, false)? {
+                    None => return Ok(false),
+                    Some(Input::Navi(NaviEvent::Forward)) => return Ok(true),
+                    Some(Input::Navi(NaviEvent::Quit)) => return Err(YexError::PartInterrupt()),
+                    Some(_) => continue,
+                }
+            }
+        }
+
+        /// Pausing until one of the keys, a Forward or the deadline
+        /// 
+        /// for preludes and relax periods, which the experimenter 
+        /// can skip with Forward. Other input is dropped, also Back, 
+        /// as there is no trial to go back to. A Quit interrupts.
+        pub fn pause(&mut self, keys: &[Key], deadline: Option<Instant>) -> Result<(), YexError> {
+            loop {
+                match self.next(deadline, !keys.is_empty())? {
+                    None | Some(Input::Navi(NaviEvent::Forward)) => return Ok(()),
+                    Some(Input::Navi(NaviEvent::Quit)) => return Err(YexError::PartInterrupt()),
+                    Some(Input::Key(key)) if keys.contains(&key) && self.allows(key) => return Ok(()),
+                    Some(_) => continue,
+                }
+            }
+        }
+
+        /// Waiting for one of the keys
+        /// 
+        /// Other keys are skipped, but every allowed key lands on the timeline. 
+        /// Returns the key and its arrival time,
+        /// or None when the deadline passed.
+        /// 
+        /// A Quit or a closed input channel interrupt the wait. A Quit also takes priority over a valid key 
+        /// when both have arrived in the same window, i.e. the Quit is already
+        /// waiting in the channel when the key is read. 
+        /// A Back ends the wait with NavigateBack.
+        pub fn wait_key(&mut self, keys: &[Key], deadline: Option<Instant>) 
+                -> Result<Option<(Key, Instant)>, YexError> {
+            loop {
+                let key = match self.next(deadline, !keys.is_empty())? {
+                    None => return Ok(None),
+                    Some(Input::Navi(NaviEvent::Quit)) => return Err(YexError::PartInterrupt()),
+                    Some(Input::Navi(NaviEvent::Back)) => return Err(YexError::NavigateBack),
+                    Some(Input::Navi(_)) | Some(Input::Release(_)) => continue,
+                    Some(Input::Key(key)) if !self.allows(key) => continue,
+                    Some(Input::Key(key)) => key,
+                };
+                let time = Instant::now();
+                if self.lockout_until.map_or(false, |until| time < until) {
+                    self.locked_out.push((key, time));
+                    continue
+                }
+                self.timeline.push((key, time));
+                if keys.contains(&key) {
+                    if self.quit_pending() {
+                        return Err(YexError::PartInterrupt())
+                    }
+                    return Ok(Some((key, time)))
+                }
+            }
+        }
+
+        /// Waiting for a chord
+        /// 
+        /// completes when all keys of the chord are down, 
+        /// pressed within the window of each other. 
+        /// Returns the key completing the chord and its time, 
+        /// Quit and Back end the wait like in wait_key.
+        pub fn wait_chord(&mut self, keys: &HashSet<Key>, window: Duration, deadline: Option<Instant>) 
+                -> Result<Option<(Key, Instant)>, YexError> {
+            let mut down: HashMap<Key, Instant> = HashMap::new();
+            loop {
+                let key = match self.next(deadline, !keys.is_empty())? {
+                    None => return Ok(None),
+                    Some(Input::Navi(NaviEvent::Quit)) => return Err(YexError::PartInterrupt()),
+                    Some(Input::Navi(NaviEvent::Back)) => return Err(YexError::NavigateBack),
+                    Some(Input::Navi(_)) => continue,
+                    Some(Input::Release(key)) => {down.remove(&key); continue},
+                    Some(Input::Key(key)) if !self.allows(key) => continue,
+                    Some(Input::Key(key)) => key,
+                };
+                let time = Instant::now();
+                if self.lockout_until.map_or(false, |until| time < until) {
+                    self.locked_out.push((key, time));
+                    continue
+                }
+                self.timeline.push((key, time));
+                if !keys.contains(&key) {continue}
+                down.insert(key, time);
+                let complete = keys.iter()
+                    .all(|key| down.get(key).map_or(false, |pressed| time - *pressed <= window));
+                if complete {
+                    if self.quit_pending() {
+                        return Err(YexError::PartInterrupt())
+                    }
+                    return Ok(Some((key, time)))
+                }
+            }
+        }
+
+        /// Reading typed text
+        /// 
+        /// until the terminator key, returning the text and the time 
+        /// of the terminator, or None when the deadline passed. 
+        /// Typed keys land on the timeline, Quit and Back end the reading like in wait_key.
+        pub fn read_text(&mut self, terminator: Key, deadline: Option<Instant>) 
+                -> Result<Option<(Text, Instant)>, YexError> {
+            let mut text = Text::new();
+            Ok(self.read_text_into(&mut text, terminator, deadline)?.map(|time| (text, time)))
+        }
+
+        /// Reading typed text into a buffer
+        /// 
+        /// like read_text, but the text typed before the deadline stays in the buffer, 
+        /// so that reading can go on after it. Returns the time of the terminator.
+        pub fn read_text_into(&mut self, text: &mut Text, terminator: Key, deadline: Option<Instant>) 
+                -> Result<Option<Instant>, YexError> {
+            loop {
+                let key = match self.next(deadline, true)? {
+                    None => return Ok(None),
+                    Some(Input::Navi(NaviEvent::Quit)) => return Err(YexError::PartInterrupt()),
+                    Some(Input::Navi(NaviEvent::Back)) => return Err(YexError::NavigateBack),
+                    Some(Input::Navi(_)) | Some(Input::Release(_)) => continue,
+                    Some(Input::Key(key)) if !self.allows(key) => continue,
+                    Some(Input::Key(key)) => key,
+                };
+                let time = Instant::now();
+                if self.lockout_until.map_or(false, |until| time < until) {
+                    self.locked_out.push((key, time));
+                    continue
+                }
+                self.timeline.push((key, time));
+                match key {
+                    key if key == terminator => {
+                        if self.quit_pending() {
+                            return Err(YexError::PartInterrupt())
+                        }
+                        return Ok(Some(time))
+                    },
+                    '\u{8}' => {text.pop();},
+                    key => text.push(key),
+                }
+            }
+        }
+
+        /// Whether a Quit has arrived together with the response
+        fn quit_pending(&mut self) -> bool {
+            self.pending.extend(self.input.try_iter());
+            self.pending.contains(&Input::Navi(NaviEvent::Quit))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::channel;
+        use crate::testing::{closed, keys};
+        use std::thread;
+
+        #[test]
+        fn chord_needs_all_keys_down_within_the_window() {
+            let chord: HashSet<Key> = ['a', 's'].into();
+            let window = Duration::from_millis(50);
+            // the input stays open until the deadline has passed
+            let chorded = |inputs: Vec<Input>, gap: Duration| {
+                let (keys, input) = channel();
+                thread::spawn(move || {
+                    for input in inputs {
+                        keys.send(input).unwrap();
+                        thread::sleep(gap);
+                    }
+                    thread::sleep(Duration::from_millis(200));
+                });
+                let deadline = Instant::now() + Duration::from_millis(150);
+                Responder::new(input).wait_chord(&chord, window, Some(deadline)).unwrap().map(|(key, _)| key)
+            };
+            assert_eq!(chorded(keys("as"), Duration::ZERO), Some('s'));
+            assert_eq!(chorded(keys("sxa"), Duration::ZERO), Some('a'));
+            assert_eq!(chorded(vec![Input::Key('a'), Input::Release('a'), Input::Key('s')], Duration::ZERO), None);
+            assert_eq!(chorded(keys("as"), Duration::from_millis(80)), None);
+        }
+
+        #[test]
+        fn back_ends_chords_and_typing() {
+            let back = || {
+                let (navi, input) = channel();
+                navi.send(Input::Navi(NaviEvent::Back)).unwrap();
+                Responder::new(input)
+            };
+            let chord: HashSet<Key> = ['a', 's'].into();
+            assert_eq!(back().wait_chord(&chord, Duration::from_millis(50), None), Err(YexError::NavigateBack));
+            assert_eq!(back().read_text('\n', None), Err(YexError::NavigateBack));
+            // nothing to go back to in a pause
+            assert_eq!(back().pause(&[], Some(Instant::now() + Duration::from_millis(10))), Ok(()));
+        }
+
+        #[test]
+        fn late_flips_complete_the_period() {
+            let (flips_out, flips) = channel();
+            let mut responder = closed();
+            responder.flips = Some(flips);
+            let from = Instant::now();
+            let to = from + Duration::from_millis(5);
+            thread::spawn(move || {
+                // reported after the period, flipped within and after it
+                thread::sleep(Duration::from_millis(20));
+                flips_out.send(from + Duration::from_millis(1)).unwrap();
+                flips_out.send(to + Duration::from_millis(10)).unwrap();
+            });
+            thread::sleep(Duration::from_millis(5));
+            // not waited for
+            let asked = Instant::now();
+            assert_eq!(responder.flip_times(from, to), vec![]);
+            assert!(asked.elapsed() < Duration::from_millis(10));
+            thread::sleep(Duration::from_millis(30));
+            assert_eq!(responder.late_flips(), vec![from + Duration::from_millis(1)]);
+            assert_eq!(responder.late_flips(), vec![]);
+            let next = to + Duration::from_millis(5);
+            assert_eq!(responder.flip_times(next, next + Duration::from_millis(10)), 
+                       vec![to + Duration::from_millis(10)]);
         }
     }
 }
 
 
-/// Trial-level
+/// Rendering
 /// 
+/// A lab has a participant screen and an experimenter screen 
+/// with different content. A renderer receives the event stream 
+/// and routes what the participant sees (stimuli, feedback, probes) 
+/// to the participant display, while progress and status go
+/// to the experimenter display.
 
-pub mod trial { 
-    use crate::output::YexRecord;
+pub mod render {
+    use super::output::{YexRecord, YexEvent};
+    use super::{session, block, Instant, Sender};
 
-    use super::{Duration, sleep, Key, Sender, YexEvent};
+    pub trait Renderer {
+        /// stimuli, feedback and probes
+        fn present_participant(&mut self, event: &YexEvent);
+        /// progress and status, one line each
+        fn present_experimenter(&mut self, status: &str);
 
-    /// A trial is a Stimulus with a Prelude and Advance frame
-    /// 
+        /// When the last frame was flipped to the participant display, 
+        /// if the renderer can tell
+        fn last_flip_time(&self) -> Option<Instant> {
+            None
+        }
 
-    #[derive(Clone, Debug, PartialEq)]
-    pub struct Trial {
-        pub prelude: Prelude,
-        pub stimulus: Stimulus,
-        pub advance: Advance,
-        pub state: State
-    }
-    
-    #[derive(Clone, PartialEq, Debug)]
-    pub enum State {
-        Init,
-        Prelude,
-        Present(Stimulus),
-        Feedback()
-    }
-    
-    impl Default for Trial {
-        fn default() -> Self {
-            Self {  state: State::Init,
-                    prelude: Prelude::Blank(Duration::from_micros(500)) ,
-                    stimulus: Stimulus::Blank(Duration::from_micros(500)),
-                    advance: Advance::Wait(Duration::from_millis(500))}
+        /// Routing an event to its display
+        fn render(&mut self, record: &YexRecord) {
+            let YexRecord(_, event) = record;
+            if for_participant(event) {
+                self.present_participant(event);
+            } else {
+                self.present_experimenter(&status(event));
+            }
         }
     }
-    
-    impl Trial {
-        
-        pub fn prepare(&mut self) -> Self{
-            self.stimulus.load();
-            self.clone()
-        }
-        pub fn run(&mut self, events_out: Sender<YexRecord>) -> Option<Observation> {
-            events_out.send(YexEvent::Trial(self.state.clone()).into()).unwrap();
-            self.prepare();
-            self.state = State::Prelude;
-            events_out.send(YexEvent::Trial(self.state.clone()).into()).unwrap();
-            match self.prelude {
-                Prelude::Now => {},
-                Prelude::Blank(dur) | Prelude::Fix(dur) 
-                    => {sleep(dur);},
-                Prelude::Prime(_,_) => todo!(),
-            }
-            self.state = State::Present(self.stimulus.clone());
-            events_out.send(YexEvent::Trial(self.state.clone()).into()).unwrap();
-            // Emulating the incoming response from the participant.
-            // 
-            // Here we will have time-outs and user events intermixed.
-            // Would be nice to have some async here, maybe 
-            // block_on(select())
-            sleep(Duration::from_millis(500));
-            let response = Response::Choice('y');
-            events_out.send(YexEvent::Response(response).into()).unwrap();
-            self.state = State::Feedback();
-            events_out.send(YexEvent::Trial(self.state.clone()).into()).unwrap();
-            match self.advance {
-                Advance::Wait(dur) => {sleep(dur)},
-                _ => {todo!();},
-            }
-            return Some(Observation::new(self.clone(), response))
+
+    /// Whether the participant sees an event
+    pub fn for_participant(event: &YexEvent) -> bool {
+        matches!(event, YexEvent::Trial(_) | YexEvent::Stimulus(_) | YexEvent::Probe(_) 
+                        | YexEvent::CalibrationTarget(_))
+    }
+
+    /// Status line for the experimenter
+    pub fn status(event: &YexEvent) -> String {
+        match event {
+            YexEvent::Session(state) => format!("Session: {}", match state {
+                session::State::Init => "Init",
+                session::State::Welcome => "Welcome",
+                session::State::Consent => "Consent",
+                session::State::Demographics => "Demographics",
+                session::State::Blocks(_) => "Blocks",
+                session::State::Goodbye => "Goodbye",
+            }),
+            YexEvent::Block(state) => format!("Block: {}", match state {
+                block::State::Init => "Init",
+                block::State::Prelude(_) => "Prelude",
+                block::State::Trials() => "Trials",
+                block::State::Relax => "Relax",
+            }),
+            YexEvent::Response(response) => match response.rt() {
+                Some(rt) => format!("Response: {} {} ms", response.kind(), rt.as_millis()),
+                None => format!("Response: {}", response.kind()),
+            },
+            YexEvent::KeyPress(key) => format!("Key: {}", key),
+            YexEvent::RelaxGated(accuracy, below) 
+                => format!("Relax: accuracy {:?}, below threshold {}", accuracy, below),
+            YexEvent::Error(error) => format!("Error: {:?}", error),
+            YexEvent::Trial(state) => format!("Trial: {:?}", state),
+            YexEvent::Stimulus(stimulus) => format!("Stimulus: {}", stimulus.kind()),
+            YexEvent::Probe(prompt) => format!("Probe: {}", prompt),
+            YexEvent::BlockSkipped(name) => format!("Block skipped: {}", name),
+            YexEvent::CalibrationTarget(target) 
+                => format!("Calibration target at ({:.2}, {:.2})", target.x, target.y),
         }
     }
 
-    #[derive(Clone, PartialEq)]
-    pub struct Observation {
-        pub trial: Trial,
-        pub response: Response,
+    /// Two displays, one for the participant and one for the experimenter
+    pub struct DualRenderer<P: Renderer, E: Renderer> {
+        pub participant: P,
+        pub experimenter: E,
     }
 
-    /// An observation is composed of a trial and an observation
+    impl<P: Renderer, E: Renderer> Renderer for DualRenderer<P, E> {
+        fn present_participant(&mut self, event: &YexEvent) {
+            self.participant.present_participant(event);
+        }
 
-    // We will need access to higher level information
-    // to add part and exp level data
+        fn present_experimenter(&mut self, status: &str) {
+            self.experimenter.present_experimenter(status);
+        }
 
-    impl Observation {
-        pub fn new(trial: Trial, response: Response) -> Self {
-            Self{trial: trial, response: response}
+        fn last_flip_time(&self) -> Option<Instant> {
+            self.participant.last_flip_time()
         }
     }
 
-    use image;
-    #[derive(Clone, Debug, PartialEq)]
-    pub enum Stimulus {
-        Blank(Duration),
-        Text(Duration, i8, [i8; 3]),
-        Image(Duration, image::RgbaImage, [usize; 4]),
+    /// Reporting frame flips back to the engine
+    /// 
+    /// wraps a renderer and sends every new flip time 
+    /// after a participant frame, to the flips of the Session or Responder.
+    pub struct FlipRecorder<R: Renderer> {
+        pub renderer: R,
+        pub flips: Sender<Instant>,
+        last: Option<Instant>,
     }
 
-    impl Stimulus{
-        pub fn load(&mut self) -> &Self
-        {self}
+    impl<R: Renderer> FlipRecorder<R> {
+        pub fn new(renderer: R, flips: Sender<Instant>) -> Self {
+            Self{renderer, flips, last: None}
+        }
     }
 
-    #[derive(Clone, PartialEq, Debug)]
-    pub enum Prelude {
-        Now,
-        Blank(Duration),
-        Fix(Duration),
-        Prime(Duration, Stimulus),
-    }
+    impl<R: Renderer> Renderer for FlipRecorder<R> {
+        fn present_participant(&mut self, event: &YexEvent) {
+            self.renderer.present_participant(event);
+            let flip = self.renderer.last_flip_time();
+            if let Some(time) = flip.filter(|_| flip != self.last) {
+                self.last = flip;
+                let _ = self.flips.send(time);
+            }
+        }
 
-    #[derive(Clone, PartialEq, Debug)]
-    pub enum Advance {
-        Wait(Duration),
-        Keys(Vec<Key>),
-        KeysMaxWait(Vec<Key>, Duration)
-    }
+        fn present_experimenter(&mut self, status: &str) {
+            self.renderer.present_experimenter(status);
+        }
 
-    #[derive(Debug, Clone, Copy, PartialEq)]
-    pub enum Response {
-        RT(Duration),
-        RTCorrect(Duration, bool),
-        Choice(Key),
-        Graded(f32),
-        TooLate,
+        fn last_flip_time(&self) -> Option<Instant> {
+            self.renderer.last_flip_time()
+        }
     }
 
-    #[derive(Clone, Copy, PartialEq)]
-    pub enum Feedback{Correct, Incorrect, ThankYou}
-}
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::trial::{self, Response, Stimulus};
+        use std::time::Duration;
 
+        /// A display keeping what it was shown, participant events by their status line
+        #[derive(Default)]
+        struct Shown {
+            events: Vec<String>,
+            lines: Vec<String>,
+        }
+
+        impl Renderer for Shown {
+            fn present_participant(&mut self, event: &YexEvent) {
+                self.events.push(status(event));
+            }
+
+            fn present_experimenter(&mut self, status: &str) {
+                self.lines.push(status.into());
+            }
+        }
+
+        #[test]
+        fn dual_renderer_routes_to_the_displays() {
+            let mut renderer = DualRenderer{participant: Shown::default(), experimenter: Shown::default()};
+            let blank = Stimulus::Blank(Duration::from_millis(10));
+            let events = [YexEvent::Block(block::State::Trials()),
+                          YexEvent::Trial(trial::State::Present(blank.clone(), None)),
+                          YexEvent::Response(Response::RT(Duration::from_millis(420))),
+                          YexEvent::Stimulus(blank),
+                          YexEvent::Probe("On task?".into())];
+            for event in events {
+                renderer.render(&event.into());
+            }
+            assert_eq!(renderer.participant.events.len(), 3);
+            assert_eq!(renderer.participant.events[1..], ["Stimulus: blank", "Probe: On task?"]);
+            assert!(renderer.participant.lines.is_empty());
+            assert!(renderer.experimenter.events.is_empty());
+            assert_eq!(renderer.experimenter.lines, ["Block: Trials", "Response: RT 420 ms"]);
+        }
+    }
+}
 
 
 /// Output
@@ -426,16 +4467,84 @@ pub mod trial {
 /// + observations
 
 pub mod output {
-    use super::{session, block, trial};
-    use super::{Key, Instant};
+    use super::{session, block, trial, input};
+    use super::{Key, Instant, Duration, Text, Sender};
+    use super::trial::Observation;
+    use std::collections::{HashMap, BTreeMap};
+    use std::io::{self, Write, BufWriter};
+    use std::fs::File;
+    use std::path::{Path, PathBuf};
     //use super::trial::{State, Stimulus, Response};
     //use super::block::State;
 
-    #[derive(Debug)]
+    #[derive(Debug, Clone, PartialEq)]
     pub enum YexError {
-        FileNotFound(),
+        /// an image file that is missing or unreadable
+        FileNotFound(PathBuf),
         PartInterrupt(),
+        /// the input channel was closed, e.g. the window
+        InputClosed,
+        /// the overall time of a run has passed
+        WatchdogTimeout,
+        /// the participant went back to the previous trial
+        NavigateBack,
+        /// a text stimulus of size zero
+        ZeroTextSize,
+        /// a text stimulus above MAX_TEXT_SIZE
+        TextTooLarge(u16),
+        /// a chord without keys
+        EmptyChord,
+        /// a condition with fewer trials than it needs
+        InsufficientTrials{condition: String, have: usize, need: usize},
+        /// no block with this name
+        BlockNotFound(String),
+        /// the experiment does not validate
+        Invalid(ValidationError),
+
+    }
+
+    /// Trigger codes at stimulus onset
+    /// 
+    /// Every condition can have its own code, so that the recording 
+    /// separates conditions. Untagged trials and conditions 
+    /// without a code get the default. The codes go to a trigger sink, 
+    /// at the other end of the channel.
+    #[derive(Debug, Clone)]
+    pub struct Triggers {
+        pub codes: HashMap<String, u8>,
+        pub default: u8,
+        pub out: Sender<u8>,
+    }
+
+    impl Triggers {
+        pub fn code(&self, condition: Option<&str>) -> u8 {
+            condition.and_then(|condition| self.codes.get(condition))
+                .copied()
+                .unwrap_or(self.default)
+        }
+
+        /// Sending the code, a sink that is gone is ignored
+        pub fn send(&self, condition: Option<&str>) {
+            let _ = self.out.send(self.code(condition));
+        }
+    }
+
+    /// Problems of a design that do not stop it from running
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum LintWarning {
+        /// where in the design and the configured duration
+        BelowTimerResolution(String, Duration),
+        /// where in the design and the correct key
+        CorrectKeyNotAllowed(String, Key),
+    }
 
+    /// Problems of a design that break features relying on it
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum ValidationError {
+        /// two or more blocks have this name
+        DuplicateBlockName(String),
+        /// block and position of a pin that does not fit
+        InconsistentPin(usize, usize),
     }
 
     #[derive(Debug)]
@@ -447,6 +4556,14 @@ pub mod output {
         Stimulus(trial::Stimulus),
         KeyPress(Key),
         Response(trial::Response),
+        /// block accuracy and whether the below branch was taken
+        RelaxGated(Option<f32>, bool),
+        /// an attention probe with its prompt
+        Probe(Text),
+        /// a block that was skipped, by name
+        BlockSkipped(Text),
+        /// an eye tracker calibration target
+        CalibrationTarget(input::Region),
     }
 
     /// Into from Event to Record
@@ -454,15 +4571,745 @@ pub mod output {
     /// simply adds Instant::now() as time stamp
     /// should therefore be used close in time
     /// to when the event arrived.
-    impl std::convert::Into<YexRecord> for YexEvent {
-        fn into(self) -> YexRecord {
-            return YexRecord(Instant::now(), self)
+    impl std::convert::From<YexEvent> for YexRecord {
+        fn from(event: YexEvent) -> YexRecord {
+            YexRecord(Instant::now(), event)
         }
     }
 
     #[derive(Debug)]
     pub struct YexRecord (pub Instant, pub YexEvent);
 
+    /// A run that ended early
+    /// 
+    /// with the observations collected until then. 
+    /// The last one is the interrupted trial.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Aborted {
+        pub error: YexError,
+        pub observations: Vec<Observation>,
+    }
+
+    impl Aborted {
+        /// Putting earlier observations in front
+        pub fn after(mut self, mut before: Vec<Observation>) -> Self {
+            before.append(&mut self.observations);
+            self.observations = before;
+            self
+        }
+    }
+
+    /// Columns of the observation table
+    /// 
+    /// + RT is in milliseconds
+    /// + RTZ is the RT z-scored within the observations, see zscore_rts
+    /// + Stimulus is the kind of stimulus
+    /// + Text is the typed text of free text responses
+    /// + Credit is the partial credit of graded responses
+    /// + Participant, Experiment, Block and Trial identify the observation, 
+    ///   Trial is the index of the trial in its block
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum Column {
+        Response,
+        RT,
+        Correct,
+        Late,
+        IsBlockFirst,
+        RTZ,
+        Condition,
+        Stimulus,
+        Feedback,
+        Reprompts,
+        Text,
+        Credit,
+        Participant,
+        Experiment,
+        Block,
+        Trial,
+    }
+
+    impl Column {
+        /// All columns, the default
+        pub const ALL: [Column; 16] = [Column::Response, Column::RT, Column::Correct, Column::Late, 
+                                       Column::IsBlockFirst, Column::RTZ, Column::Condition, 
+                                       Column::Stimulus, Column::Feedback, Column::Reprompts, 
+                                       Column::Text, Column::Credit, Column::Participant, 
+                                       Column::Experiment, Column::Block, Column::Trial];
+
+        pub fn name(&self) -> &'static str {
+            match self {
+                Column::Response => "response",
+                Column::RT => "rt",
+                Column::Correct => "correct",
+                Column::Late => "late",
+                Column::IsBlockFirst => "is_block_first",
+                Column::RTZ => "rt_z",
+                Column::Condition => "condition",
+                Column::Stimulus => "stimulus",
+                Column::Feedback => "feedback",
+                Column::Reprompts => "reprompts",
+                Column::Text => "text",
+                Column::Credit => "credit",
+                Column::Participant => "participant",
+                Column::Experiment => "experiment",
+                Column::Block => "block",
+                Column::Trial => "trial",
+            }
+        }
+
+        /// The value of an observation, empty if missing
+        fn value(&self, obs: &Observation, rt_z: Option<f32>) -> String {
+            match self {
+                Column::Response => obs.response.kind().into(),
+                Column::RT => Metric::RT.value(obs).map(|rt| rt.to_string()).unwrap_or_default(),
+                Column::Correct => obs.response.correct().map(|c| c.to_string()).unwrap_or_default(),
+                Column::Late => obs.late.to_string(),
+                Column::IsBlockFirst => obs.is_block_first.to_string(),
+                Column::RTZ => rt_z.map(|z| z.to_string()).unwrap_or_default(),
+                Column::Condition => obs.trial.condition.clone().unwrap_or_default(),
+                Column::Stimulus => obs.trial.stimulus.kind().into(),
+                Column::Feedback => match obs.feedback {
+                    None => "".into(),
+                    Some(trial::Feedback::Correct) => "Correct".into(),
+                    Some(trial::Feedback::Incorrect) => "Incorrect".into(),
+                    Some(trial::Feedback::ThankYou) => "ThankYou".into(),
+                    Some(trial::Feedback::CorrectAnswer(..)) => "CorrectAnswer".into(),
+                },
+                Column::Reprompts => obs.reprompts.to_string(),
+                Column::Text => obs.response.text().unwrap_or_default().into(),
+                Column::Credit => obs.response.credit().map(|c| c.to_string()).unwrap_or_default(),
+                Column::Participant => obs.participant.to_string(),
+                Column::Experiment => obs.experiment.to_string(),
+                Column::Block => obs.block.to_string(),
+                Column::Trial => obs.trial_index.to_string(),
+            }
+        }
+    }
+
+    /// Writing observations as a table
+    /// 
+    /// one header row, then one row per observation, 
+    /// with exactly the given columns in the given order.
+    /// Values containing the separator or quotes are quoted.
+    pub fn write_table<W: Write>(observations: &[Observation], columns: &[Column], sep: char, mut w: W) 
+            -> io::Result<()> {
+        let header: Vec<&str> = columns.iter().map(|col| col.name()).collect();
+        writeln!(w, "{}", header.join(&sep.to_string()))?;
+        let rt_z = zscore_rts(observations);
+        for (obs, rt_z) in observations.iter().zip(rt_z) {
+            let row: Vec<String> = columns.iter()
+                .map(|col| quote(col.value(obs, rt_z), sep))
+                .collect();
+            writeln!(w, "{}", row.join(&sep.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn quote(value: String, sep: char) -> String {
+        if value.contains(sep) || value.contains('"') || value.contains('\n') {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value
+        }
+    }
+
+    /// Writing observations as CSV, with all columns
+    pub fn write_csv<W: Write>(observations: &[Observation], w: W) -> io::Result<()> {
+        write_table(observations, &Column::ALL, ',', w)
+    }
+
+    /// Recording the events of a session
+    /// 
+    /// e.g. at the receiving end of the events channel, 
+    /// for writing them after the session. 
+    /// Times are from the start of the recording.
+    #[derive(Debug)]
+    pub struct Recorder {
+        pub start: Instant,
+        pub records: Vec<YexRecord>,
+    }
+
+    impl Default for Recorder {
+        fn default() -> Self {
+            Self{start: Instant::now(), records: Vec::new()}
+        }
+    }
+
+    impl Recorder {
+        pub fn record(&mut self, record: YexRecord) {
+            self.records.push(record);
+        }
+
+        /// Writing the events as CSV
+        /// 
+        /// with the columns time (ms), event and value, one row per event. 
+        /// Values with several parts are separated by colons, 
+        /// e.g. RTCorrect:412.5:true for a response.
+        pub fn write_csv<W: Write>(&self, w: W) -> io::Result<()> {
+            let mut w = BufWriter::new(w);
+            writeln!(w, "time,event,value")?;
+            for YexRecord(time, event) in self.records.iter() {
+                let time = time.saturating_duration_since(self.start).as_secs_f64() * 1000.;
+                let (kind, value) = encode_event(event);
+                writeln!(w, "{},{},{}", time, kind, quote(value, ','))?;
+            }
+            w.flush()
+        }
+    }
+
+    fn encode_event(event: &YexEvent) -> (&'static str, String) {
+        match event {
+            YexEvent::Error(error) => ("Error", format!("{:?}", error)),
+            YexEvent::Session(state) => ("Session", match state {
+                session::State::Init => "Init".into(),
+                session::State::Welcome => "Welcome".into(),
+                session::State::Consent => "Consent".into(),
+                session::State::Demographics => "Demographics".into(),
+                session::State::Blocks(block) => format!("Blocks:{}", block.name),
+                session::State::Goodbye => "Goodbye".into(),
+            }),
+            YexEvent::Block(state) => ("Block", match state {
+                block::State::Init => "Init",
+                block::State::Prelude(_) => "Prelude",
+                block::State::Trials() => "Trials",
+                block::State::Relax => "Relax",
+            }.into()),
+            YexEvent::Trial(state) => ("Trial", match state {
+                trial::State::Init => "Init".into(),
+                trial::State::Prelude => "Prelude".into(),
+                trial::State::Present(stimulus, _) => format!("Present:{}", stimulus.kind()),
+                trial::State::Feedback(feedback) => format!("Feedback:{:?}", feedback),
+            }),
+            YexEvent::Stimulus(stimulus) => ("Stimulus", stimulus.kind().into()),
+            YexEvent::KeyPress(key) => ("KeyPress", key.to_string()),
+            YexEvent::Response(response) => ("Response", encode_response(response)),
+            YexEvent::RelaxGated(accuracy, below) 
+                => ("RelaxGated", format!("{}:{}", accuracy.map(|a| a.to_string()).unwrap_or_default(), below)),
+            YexEvent::Probe(prompt) => ("Probe", prompt.clone()),
+            YexEvent::BlockSkipped(name) => ("BlockSkipped", name.clone()),
+            YexEvent::CalibrationTarget(target) 
+                => ("CalibrationTarget", format!("{}:{}:{}", target.x, target.y, target.radius)),
+        }
+    }
+
+    /// The kind of response and its payload, RT in ms
+    fn encode_response(response: &trial::Response) -> String {
+        use trial::Response;
+        let ms = |rt: &Duration| rt.as_secs_f64() * 1000.;
+        match response {
+            Response::RT(rt) => format!("RT:{}", ms(rt)),
+            Response::RTCorrect(rt, correct) => format!("RTCorrect:{}:{}", ms(rt), correct),
+            Response::Choice(key) => format!("Choice:{}", key),
+            Response::Graded(grade) => format!("Graded:{}", grade),
+            Response::GradedCorrect(credit) => format!("GradedCorrect:{}", credit),
+            Response::Text(text) => format!("Text:{}", text),
+            Response::TooLate | Response::Interrupted => response.kind().into(),
+        }
+    }
+
+    /// Writing observations as TSV, with all columns
+    pub fn write_tsv<W: Write>(observations: &[Observation], w: W) -> io::Result<()> {
+        write_table(observations, &Column::ALL, '\t', w)
+    }
+
+    /// Writing one CSV file per block
+    /// 
+    /// into dir, as block_<index>.csv with the observations of that block, 
+    /// plus a session.json manifest listing the files. 
+    /// Every block of the experiment gets a file, even without observations. 
+    /// RT z-scores are computed within the block.
+    pub fn write_per_block<P: AsRef<Path>>(session: &session::Session, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        let mut files = Vec::new();
+        for index in 0..session.exp.blocks.len() {
+            let observations: Vec<Observation> = session.observations.iter()
+                .filter(|obs| obs.block == index)
+                .cloned()
+                .collect();
+            let file = format!("block_{}.csv", index);
+            let mut w = BufWriter::new(File::create(dir.join(&file))?);
+            write_csv(&observations, &mut w)?;
+            w.flush()?;
+            files.push(format!("{{\"block\": {}, \"file\": \"{}\", \"observations\": {}}}", 
+                               index, file, observations.len()));
+        }
+        let mut w = BufWriter::new(File::create(dir.join("session.json"))?);
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"experiment\": \"{}\",", json_escape(&session.exp.id))?;
+        writeln!(w, "  \"participant\": {},", session.part.id)?;
+        writeln!(w, "  \"seed\": {},", session.seed)?;
+        writeln!(w, "  \"blocks\": [")?;
+        writeln!(w, "    {}", files.join(",\n    "))?;
+        writeln!(w, "  ]")?;
+        writeln!(w, "}}")?;
+        w.flush()
+    }
+
+    /// Writing an observation as one line of JSON
+    /// 
+    /// flattened to the columns of the observation table, 
+    /// with numbers and booleans unquoted and missing values as null. 
+    /// RTZ needs all observations and is left out.
+    pub fn write_json_line<W: Write + ?Sized>(obs: &Observation, w: &mut W) -> io::Result<()> {
+        let fields: Vec<String> = Column::ALL.iter()
+            .filter(|column| !matches!(column, Column::RTZ))
+            .map(|column| {
+                let value = column.value(obs, None);
+                let value = match column {
+                    _ if value.is_empty() => "null".into(),
+                    Column::RT | Column::Correct | Column::Late | Column::IsBlockFirst 
+                        | Column::Reprompts | Column::Credit | Column::Trial 
+                        | Column::Participant | Column::Block => value,
+                    _ => format!("\"{}\"", json_escape(&value)),
+                };
+                format!("\"{}\": {}", column.name(), value)})
+            .collect();
+        writeln!(w, "{{{}}}", fields.join(", "))?;
+        w.flush()
+    }
+
+    /// Escaping a JSON string, control characters included
+    fn json_escape(value: &str) -> String {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            match c {
+                '"' => escaped.push_str("\\\""),
+                '\\' => escaped.push_str("\\\\"),
+                '\n' => escaped.push_str("\\n"),
+                '\r' => escaped.push_str("\\r"),
+                '\t' => escaped.push_str("\\t"),
+                c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+                c => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    /// Writing a BIDS events.tsv
+    /// 
+    /// one row per stimulus onset, with the columns onset, duration, 
+    /// trial_type and response_time. All times are in seconds, onsets 
+    /// are relative to time_zero, usually the start of the scanner run. 
+    /// The trial_type is the condition of the trial.
+    /// The response time is taken from the next response event, 
+    /// missing values are n/a, as BIDS demands.
+    pub fn write_bids_events<P: AsRef<Path>>(block_events: &[YexRecord], time_zero: Instant, path: P) 
+            -> io::Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        writeln!(w, "onset\tduration\ttrial_type\tresponse_time")?;
+        for (i, YexRecord(time, event)) in block_events.iter().enumerate() {
+            let YexEvent::Trial(trial::State::Present(stim, condition)) = event else {continue};
+            let response_time = block_events[i + 1..].iter()
+                .find_map(|YexRecord(_, event)| match event {
+                    YexEvent::Response(response) => Some(response.rt()),
+                    _ => None,
+                })
+                .flatten()
+                .map(|rt| format!("{:.4}", rt.as_secs_f64()))
+                .unwrap_or("n/a".into());
+            writeln!(w, "{:.4}\t{:.4}\t{}\t{}", 
+                     time.saturating_duration_since(time_zero).as_secs_f64(),
+                     stim.duration().as_secs_f64(),
+                     condition.as_deref().unwrap_or("n/a"),
+                     response_time)?;
+        }
+        w.flush()
+    }
+
+    /// Summary of observations
+    /// 
+    /// + trials is the number of completed (not interrupted) trials
+    /// + accuracy among trials with a correct answer, or partial credit
+    /// + mean_rt over correct trials and trials without a correct answer
+    /// + credit is the total partial credit of graded trials
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct Summary {
+        pub trials: usize,
+        pub accuracy: Option<f32>,
+        pub mean_rt: Option<Duration>,
+        pub credit: Option<f32>,
+    }
+
+    pub fn summarize(observations: &[Observation]) -> Summary {
+        let trials = observations.iter().filter(|obs| !obs.interrupted()).count();
+        let scored: Vec<f64> = observations.iter().filter_map(|obs| Metric::Accuracy.value(obs)).collect();
+        let accuracy = if scored.is_empty() {None} else {Some(mean(&scored) as f32)};
+        let rts: Vec<f64> = observations.iter()
+            .filter(|obs| obs.response.correct() != Some(false))
+            .filter_map(|obs| Metric::RT.value(obs))
+            .collect();
+        let mean_rt = if rts.is_empty() {None} else {Some(Duration::from_secs_f64(mean(&rts) / 1000.))};
+        let credits: Vec<f32> = observations.iter().filter_map(|obs| obs.response.credit()).collect();
+        let credit = if credits.is_empty() {None} else {Some(credits.iter().sum())};
+        Summary{trials, accuracy, mean_rt, credit}
+    }
+
+    impl std::fmt::Display for Summary {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "{} trials", self.trials)?;
+            if let Some(accuracy) = self.accuracy {
+                write!(f, ", accuracy {:.1}%", accuracy * 100.)?;
+            }
+            if let Some(mean_rt) = self.mean_rt {
+                write!(f, ", mean RT {:.0} ms", mean_rt.as_secs_f64() * 1000.)?;
+            }
+            if let Some(credit) = self.credit {
+                write!(f, ", credit {:.1}", credit)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Congruency effect, incongruent minus congruent
+    /// 
+    /// + rt_effect is the difference in mean RT of correct trials, in ms
+    /// + accuracy_effect is the difference in accuracy
+    /// + n_per_cell counts the completed congruent and incongruent trials
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub struct EffectResult {
+        pub rt_effect: Option<f64>,
+        pub accuracy_effect: Option<f64>,
+        pub n_per_cell: (usize, usize),
+    }
+
+    /// Stroop-type congruency effect
+    /// 
+    /// splits the observations by their condition tag. 
+    /// An effect is None when a cell has no data for it.
+    pub fn congruency_effect(observations: &[Observation], congruent: &str, incongruent: &str) -> EffectResult {
+        let cell = |condition: &str| -> Vec<Observation> {
+            observations.iter()
+                .filter(|obs| obs.trial.condition.as_deref() == Some(condition))
+                .cloned()
+                .collect()
+        };
+        let (con, inc) = (summarize(&cell(congruent)), summarize(&cell(incongruent)));
+        let ms = |rt: Duration| rt.as_secs_f64() * 1000.;
+        let rt_effect = match (con.mean_rt, inc.mean_rt) {
+            (Some(con), Some(inc)) => Some(ms(inc) - ms(con)),
+            _ => None,
+        };
+        let accuracy_effect = match (con.accuracy, inc.accuracy) {
+            (Some(con), Some(inc)) => Some(inc as f64 - con as f64),
+            _ => None,
+        };
+        EffectResult{rt_effect, accuracy_effect, n_per_cell: (con.trials, inc.trials)}
+    }
+
+    /// RT quantiles per condition, e.g. for delta plots
+    /// 
+    /// over the RTs (ms) of correct trials and trials without a correct key, 
+    /// with linear interpolation between the order statistics. 
+    /// Trials without a condition tag are left out.
+    pub fn rt_quantiles_by_condition(observations: &[Observation], quantiles: &[f64]) 
+            -> HashMap<String, Vec<f64>> {
+        let mut rts: HashMap<String, Vec<f64>> = HashMap::new();
+        for obs in observations.iter().filter(|obs| obs.response.correct() != Some(false)) {
+            let (Some(condition), Some(rt)) = (&obs.trial.condition, Metric::RT.value(obs)) 
+                else {continue};
+            rts.entry(condition.clone()).or_default().push(rt);
+        }
+        rts.into_iter()
+            .map(|(condition, mut rts)| {
+                rts.sort_by(|a, b| a.total_cmp(b));
+                let quantiles = quantiles.iter().map(|q| quantile(&rts, *q)).collect();
+                (condition, quantiles)
+            })
+            .collect()
+    }
+
+    /// Quantile of sorted values
+    fn quantile(sorted: &[f64], q: f64) -> f64 {
+        let pos = q.clamp(0., 1.) * (sorted.len() - 1) as f64;
+        let (lower, upper) = (pos.floor() as usize, pos.ceil() as usize);
+        sorted[lower] + (sorted[upper] - sorted[lower]) * (pos - lower as f64)
+    }
+
+    /// Per-trial measures for analysis
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum Metric {
+        /// RT in milliseconds
+        RT,
+        /// correct as 1, incorrect as 0
+        Accuracy,
+    }
+
+    impl Metric {
+        pub fn value(&self, obs: &Observation) -> Option<f64> {
+            match self {
+                Metric::RT => obs.response.rt().map(|rt| rt.as_secs_f64() * 1000.),
+                Metric::Accuracy => obs.response.accuracy().map(f64::from),
+            }
+        }
+    }
+
+    /// Split-half reliability, with an odd/even split
+    /// 
+    /// The trials of every cell, a block and condition, are split into two halves. 
+    /// The mean metric of the cells in one half is correlated with the other half, 
+    /// and corrected with the Spearman-Brown formula, 2r/(1 + r). 
+    /// Trials without a value for the metric are left out.
+    /// 
+    /// Returns None if fewer than three cells have values in both halves, 
+    /// without variance, and for a correlation of -1 or below.
+    pub fn split_half_reliability(observations: &[Observation], metric: Metric) -> Option<f64> {
+        split_half_reliability_with(observations, metric, Split::OddEven)
+    }
+
+    /// Splitting the trials of a cell into halves
+    /// 
+    /// + OddEven alternates in the order of presentation
+    /// + Random shuffles the trials with a seed, first half against second
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum Split {
+        OddEven,
+        Random(u64),
+    }
+
+    /// Split-half reliability, see split_half_reliability
+    pub fn split_half_reliability_with(observations: &[Observation], metric: Metric, split: Split) 
+            -> Option<f64> {
+        use rand::{SeedableRng, seq::SliceRandom};
+        let mut cells: BTreeMap<_, Vec<f64>> = BTreeMap::new();
+        for obs in observations {
+            let Some(value) = metric.value(obs) else {continue};
+            cells.entry((obs.block, obs.trial.condition.as_deref())).or_default().push(value);
+        }
+        let mut rng = match split {
+            Split::Random(seed) => Some(session::SessionRng::seed_from_u64(seed)),
+            Split::OddEven => None,
+        };
+        let (first, second): (Vec<f64>, Vec<f64>) = cells.into_values()
+            .filter_map(|mut values| {
+                let (first, second): (Vec<f64>, Vec<f64>) = match rng.as_mut() {
+                    Some(rng) => {
+                        values.shuffle(rng);
+                        let second = values.split_off(values.len() / 2);
+                        (values, second)
+                    },
+                    None => (values.iter().step_by(2).copied().collect(), 
+                             values.iter().skip(1).step_by(2).copied().collect()),
+                };
+                if first.is_empty() || second.is_empty() {return None}
+                Some((mean(&first), mean(&second)))
+            })
+            .unzip();
+        if first.len() < 3 {return None}
+        let r = correlation(&first, &second)?;
+        if r <= -1. {return None}
+        Some(2. * r / (1. + r))
+    }
+
+    /// Z-scored RTs
+    /// 
+    /// standardizes the RT of each correct trial with the mean and SD 
+    /// of all correct trials, which normalizes individual speed differences. 
+    /// Trials with an RT but no notion of correctness count as correct.
+    /// None for incorrect trials, responses without RT, 
+    /// and if there are less than two RTs.
+    pub fn zscore_rts(observations: &[Observation]) -> Vec<Option<f32>> {
+        let rts: Vec<Option<f64>> = observations.iter()
+            .map(|obs| match obs.response.correct() {
+                Some(false) => None,
+                _ => Metric::RT.value(obs),
+            })
+            .collect();
+        let valid: Vec<f64> = rts.iter().flatten().copied().collect();
+        let sd = sd(&valid);
+        let mean = mean(&valid);
+        rts.iter()
+            .map(|rt| match (rt, sd) {
+                (Some(rt), Some(sd)) => Some(((rt - mean) / sd) as f32),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn mean(x: &[f64]) -> f64 {
+        x.iter().sum::<f64>() / x.len() as f64
+    }
+
+    /// Sample standard deviation, None below two values or without variance
+    fn sd(x: &[f64]) -> Option<f64> {
+        if x.len() < 2 {return None}
+        let m = mean(x);
+        let sd = (x.iter().map(|x| (x - m).powi(2)).sum::<f64>() / (x.len() - 1) as f64).sqrt();
+        if sd == 0. {None} else {Some(sd)}
+    }
+
+    /// Pearson correlation, None without variance
+    fn correlation(x: &[f64], y: &[f64]) -> Option<f64> {
+        let (mx, my) = (mean(x), mean(y));
+        let sxy: f64 = x.iter().zip(y).map(|(x, y)| (x - mx) * (y - my)).sum();
+        let sxx: f64 = x.iter().map(|x| (x - mx).powi(2)).sum();
+        let syy: f64 = y.iter().map(|y| (y - my).powi(2)).sum();
+        if sxx == 0. || syy == 0. {return None}
+        Some(sxy / (sxx * syy).sqrt())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::Duration;
+        use crate::trial::{Trial, Response};
+
+        /// An observation with an RT in ms and a condition
+        fn rt(ms: u64, condition: &str) -> Observation {
+            Observation::new(Trial{condition: Some(condition.into()), ..Trial::default()}, 
+                             Response::RT(Duration::from_millis(ms)))
+        }
+
+        #[test]
+        fn zscores_are_standardized() {
+            let mut obs: Vec<Observation> = [320, 410, 385, 520, 290, 450, 600, 375]
+                .map(|ms| rt(ms, "a")).to_vec();
+            obs.push(Observation::new(Trial::default(), Response::RTCorrect(Duration::from_millis(900), false)));
+            obs.push(Observation::new(Trial::default(), Response::TooLate));
+            let z = zscore_rts(&obs);
+            assert_eq!(z.len(), obs.len());
+            assert_eq!(z[8..], [None, None]);
+            let z: Vec<f64> = z.iter().flatten().map(|z| *z as f64).collect();
+            assert_eq!(z.len(), 8);
+            assert!(mean(&z).abs() < 1e-6);
+            assert!((sd(&z).unwrap() - 1.).abs() < 1e-6);
+        }
+
+        #[test]
+        fn table_has_the_given_columns_in_order() {
+            let obs = vec![rt(320, "a, b"), rt(410, "c")];
+            let mut table = Vec::new();
+            write_table(&obs, &[Column::Condition, Column::RT, Column::Response], ';', &mut table).unwrap();
+            assert_eq!(String::from_utf8(table).unwrap(), "condition;rt;response\na, b;320;RT\nc;410;RT\n");
+            let mut table = Vec::new();
+            write_table(&obs, &[Column::RT, Column::Condition], ',', &mut table).unwrap();
+            assert_eq!(String::from_utf8(table).unwrap(), "rt,condition\n320,\"a, b\"\n410,c\n");
+        }
+
+        #[test]
+        fn congruency_effect_of_synthetic_data() {
+            let scored = |ms: u64, correct: bool, condition: &str| 
+                Observation::new(Trial{condition: Some(condition.into()), ..Trial::default()}, 
+                                 Response::RTCorrect(Duration::from_millis(ms), correct));
+            let obs = vec![scored(400, true, "con"), scored(420, true, "con"), scored(300, false, "con"),
+                           scored(470, true, "inc"), scored(490, true, "inc"), scored(510, true, "inc")];
+            let effect = congruency_effect(&obs, "con", "inc");
+            // the error does not go into the mean RT
+            assert!((effect.rt_effect.unwrap() - 80.).abs() < 1e-6);
+            assert!((effect.accuracy_effect.unwrap() - 1. / 3.).abs() < 1e-6);
+            assert_eq!(effect.n_per_cell, (3, 3));
+            let missing = congruency_effect(&obs, "con", "neutral");
+            assert_eq!((missing.rt_effect, missing.accuracy_effect, missing.n_per_cell), (None, None, (3, 0)));
+        }
+
+        #[test]
+        fn quantiles_interpolate_within_conditions() {
+            let mut obs: Vec<Observation> = [400, 100, 300, 200].map(|ms| rt(ms, "a")).to_vec();
+            obs.push(rt(500, "b"));
+            obs.push(Observation::new(Trial{condition: Some("b".into()), ..Trial::default()}, 
+                                      Response::RTCorrect(Duration::from_millis(900), false)));
+            obs.push(Observation::new(Trial::default(), Response::RT(Duration::from_millis(700))));
+            let quantiles = rt_quantiles_by_condition(&obs, &[0., 0.25, 0.5, 1.]);
+            assert_eq!(quantiles.len(), 2);
+            assert_eq!(quantiles["a"], [100., 175., 250., 400.]);
+            // the error is left out
+            assert_eq!(quantiles["b"], [500.; 4]);
+        }
+
+        #[test]
+        fn recorder_writes_a_row_per_event() {
+            let mut recorder = Recorder::default();
+            let at = |ms: u64, event: YexEvent| YexRecord(recorder.start + Duration::from_millis(ms), event);
+            let events = vec![
+                at(10, YexEvent::Stimulus(trial::Stimulus::Blank(Duration::from_millis(200)))),
+                at(250, YexEvent::KeyPress('f')),
+                at(250, YexEvent::Response(Response::RTCorrect(Duration::from_micros(240_500), true))),
+                at(900, YexEvent::Response(Response::Text("red, green".into()))),
+                at(1500, YexEvent::Response(Response::TooLate))];
+            for record in events {
+                recorder.record(record);
+            }
+            let mut csv = Vec::new();
+            recorder.write_csv(&mut csv).unwrap();
+            assert_eq!(String::from_utf8(csv).unwrap(), "time,event,value\n\
+                                                         10,Stimulus,blank\n\
+                                                         250,KeyPress,f\n\
+                                                         250,Response,RTCorrect:240.5:true\n\
+                                                         900,Response,\"Text:red, green\"\n\
+                                                         1500,Response,TooLate\n");
+        }
+
+        #[test]
+        fn json_lines_escape_control_characters() {
+            let mut line = Vec::new();
+            write_json_line(&rt(450, "a\tb\n\"c\u{1}"), &mut line).unwrap();
+            let line = String::from_utf8(line).unwrap();
+            assert!(line.contains(r#""a\tb\n\"c\u0001""#), "{line}");
+            assert!(line.contains(r#""rt": 450"#), "{line}");
+            assert_eq!(line.matches('\n').count(), 1);
+            assert!(line.ends_with("}\n") && !line[..line.len() - 1].chars().any(char::is_control));
+        }
+
+        #[test]
+        fn bids_onsets_are_seconds_from_time_zero() {
+            use trial::{State, Stimulus};
+            let time_zero = Instant::now();
+            let at = |ms: u64, event: YexEvent| YexRecord(time_zero + Duration::from_millis(ms), event);
+            let present = |condition: Option<&str>| YexEvent::Trial(
+                State::Present(Stimulus::Blank(Duration::from_millis(200)), condition.map(Into::into)));
+            let events = vec![
+                at(1500, present(Some("congruent"))),
+                at(1950, YexEvent::Response(Response::RT(Duration::from_millis(450)))),
+                at(3250, present(None)),
+                at(4250, YexEvent::Response(Response::TooLate))];
+            let path = std::env::temp_dir().join(format!("yex_events_{}.tsv", std::process::id()));
+            write_bids_events(&events, time_zero, &path).unwrap();
+            let tsv = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(tsv, "onset\tduration\ttrial_type\tresponse_time\n\
+                             1.5000\t0.2000\tcongruent\t0.4500\n\
+                             3.2500\t0.2000\tn/a\tn/a\n");
+        }
+
+        #[test]
+        fn per_block_files_and_their_manifest() {
+            use crate::session::{Experiment, Participant, Session};
+            let mut session = Session::new(Experiment::default(), Participant{id: 7, ..Participant::default()});
+            session.seed = 3;
+            let in_block = |block: usize| Observation{block, ..rt(400, "a")};
+            session.observations = vec![in_block(0), in_block(1), in_block(0)];
+            let dir = std::env::temp_dir().join(format!("yex_blocks_{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            write_per_block(&session, &dir).unwrap();
+            let rows = |file: &str| std::fs::read_to_string(dir.join(file)).unwrap().lines().count();
+            // a header and a row per observation
+            assert_eq!((rows("block_0.csv"), rows("block_1.csv")), (3, 2));
+            let manifest = std::fs::read_to_string(dir.join("session.json")).unwrap();
+            std::fs::remove_dir_all(&dir).unwrap();
+            assert_eq!(manifest, "{\n  \"experiment\": \"Stroop\",\n  \"participant\": 7,\n  \"seed\": 3,\n  \"blocks\": [\n    \
+                                  {\"block\": 0, \"file\": \"block_0.csv\", \"observations\": 2},\n    \
+                                  {\"block\": 1, \"file\": \"block_1.csv\", \"observations\": 1}\n  ]\n}\n");
+        }
+
+        #[test]
+        fn consistent_cells_are_reliable() {
+            // five conditions of distinct speed, with a little noise
+            let observations: Vec<Observation> = (0..10u64)
+                .flat_map(|i| ["a", "b", "c", "d", "e"].into_iter().enumerate()
+                    .map(move |(c, condition)| rt(300 + 100 * c as u64 + (i * 7) % 20, condition)))
+                .collect();
+            assert!(split_half_reliability(&observations, Metric::RT).unwrap() > 0.95);
+            let random = split_half_reliability_with(&observations, Metric::RT, Split::Random(3)).unwrap();
+            assert!(random > 0.95);
+        }
+
+        #[test]
+        fn opposite_halves_are_not_reliable() {
+            let observations = vec![rt(100, "a"), rt(300, "a"), rt(200, "b"), rt(200, "b"), 
+                                    rt(300, "c"), rt(100, "c")];
+            assert_eq!(split_half_reliability(&observations, Metric::RT), None);
+        }
+    }
+
     /* use std::fmt::{Display, Formatter, Result};
     impl std::fmt::Display for YexRecord {
         // This trait requires `fmt` with this exact signature.
@@ -476,3 +5323,48 @@ pub mod output {
     }*/
 
 }
+
+
+/// Helpers for the tests
+#[cfg(test)]
+mod testing {
+    use super::{channel, sleep, Duration, Input, NaviEvent, Receiver};
+    use super::input::Responder;
+
+    /// A responder whose input is already closed
+    pub fn closed() -> Responder {
+        let (_, input) = channel::<Input>();
+        Responder::new(input)
+    }
+
+    /// A responder getting the inputs one after the other, with a gap before each, 
+    /// so that they arrive in the response window
+    pub fn typing(inputs: Vec<Input>, gap: Duration) -> Responder {
+        Responder::new(typed(inputs, gap))
+    }
+
+    /// The input channel of typing
+    pub fn typed(inputs: Vec<Input>, gap: Duration) -> Receiver<Input> {
+        let (keys, input) = channel();
+        std::thread::spawn(move || 
+            for input in inputs {
+                sleep(gap);
+                if keys.send(input).is_err() {break}
+            });
+        input
+    }
+
+    /// Input that goes through Welcome, Consent and Demographics, then closes
+    pub fn forwarded() -> Receiver<Input> {
+        let (keys, input) = channel();
+        for _ in 0..3 {
+            keys.send(Input::Navi(NaviEvent::Forward)).unwrap();
+        }
+        input
+    }
+
+    /// Keys as input
+    pub fn keys(keys: &str) -> Vec<Input> {
+        keys.chars().map(Input::Key).collect()
+    }
+}